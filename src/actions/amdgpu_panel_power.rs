@@ -0,0 +1,82 @@
+use std::sync::Arc;
+
+use anyhow::Result;
+use async_trait::async_trait;
+
+use crate::sysfs::Sysfs;
+
+const DRM: &str = "/sys/class/drm";
+
+/// Lets amdgpu trade color accuracy for power on built-in panels in power-saver
+pub(crate) struct Action {
+    /// DRM connectors of built-in panels, e.g. card1-eDP-1
+    panels: Vec<String>,
+    sysfs: Sysfs,
+}
+
+impl Action {
+    /// Most aggressive level amdgpu supports, 0 disables the savings
+    const POWER_SAVER_LEVEL: &'static str = "3";
+    const DISABLED_LEVEL: &'static str = "0";
+
+    fn panel_power_savings(panel: &str) -> String {
+        format!("{}/{}/amdgpu/panel_power_savings", DRM, panel)
+    }
+}
+
+#[async_trait]
+impl crate::actions::Action for Action {
+    async fn activate(&self, power_profile: &crate::types::PowerProfile) -> Result<()> {
+        let level = match power_profile.name.as_str() {
+            crate::holds::POWER_SAVER => Self::POWER_SAVER_LEVEL,
+            _ => Self::DISABLED_LEVEL,
+        };
+
+        for panel in &self.panels {
+            log::debug!("Setting panel power savings of {} to {}", panel, level);
+
+            self.sysfs
+                .write(Self::panel_power_savings(panel), level)
+                .await?;
+        }
+
+        Ok(())
+    }
+
+    async fn status(&self) -> Result<String> {
+        let mut statuses = Vec::new();
+
+        for panel in &self.panels {
+            let level = self.sysfs.read(Self::panel_power_savings(panel)).await?;
+
+            statuses.push(format!("{}: {}", panel, level));
+        }
+
+        Ok(statuses.join(", "))
+    }
+
+    fn name(&self) -> &str {
+        "amdgpu_panel_power"
+    }
+}
+
+pub async fn probe(sysfs: &Sysfs) -> Result<Arc<dyn crate::actions::Action + Send + Sync>> {
+    let mut panels = Vec::new();
+
+    for connector in sysfs.list(DRM, "card").await? {
+        if connector.contains("-eDP-")
+            && sysfs.exists(Action::panel_power_savings(&connector)).await
+        {
+            panels.push(connector);
+        }
+    }
+
+    if panels.is_empty() {
+        return Err(anyhow::anyhow!("no amdgpu panel with power savings"));
+    }
+
+    Ok(Arc::new(Action {
+        panels,
+        sysfs: sysfs.clone(),
+    }))
+}
@@ -0,0 +1,91 @@
+use std::sync::Arc;
+
+use anyhow::Result;
+use async_trait::async_trait;
+
+use crate::{settings::Settings, sysfs::Sysfs};
+
+mod amdgpu_panel_power;
+mod trickle_charge;
+
+/// Something done on top of the drivers whenever a profile is activated, like upstream's
+/// actions. Failing actions never fail the activation.
+#[async_trait]
+pub(crate) trait Action: Send + Sync {
+    async fn activate(&self, power_profile: &crate::types::PowerProfile) -> Result<()>;
+    /// What the action currently does to the hardware, for the extension interface
+    async fn status(&self) -> Result<String>;
+    fn name(&self) -> &str;
+}
+
+#[derive(Clone, Default)]
+pub(crate) struct ActionSet {
+    actions: Vec<Arc<dyn Action + Send + Sync>>,
+}
+
+impl ActionSet {
+    pub fn names(&self) -> Vec<String> {
+        self.actions
+            .iter()
+            .map(|action| action.name().to_owned())
+            .collect()
+    }
+
+    /// Activate a profile on every action, failures are only logged
+    pub async fn activate(&self, power_profile: &crate::types::PowerProfile) {
+        for action in &self.actions {
+            if let Err(err) = action.activate(power_profile).await {
+                log::warn!(
+                    "Action {} failed for {}: {:#}",
+                    action.name(),
+                    power_profile.name,
+                    err
+                );
+            }
+        }
+    }
+
+    /// The (name, status) of every action
+    pub async fn status(&self) -> Vec<(String, String)> {
+        let mut statuses = Vec::new();
+
+        for action in &self.actions {
+            let status = match action.status().await {
+                Ok(status) => status,
+                Err(err) => format!("unknown ({:#})", err),
+            };
+
+            statuses.push((action.name().to_owned(), status));
+        }
+
+        statuses
+    }
+}
+
+pub(crate) async fn probe(settings: &Settings, sysfs: &Sysfs) -> ActionSet {
+    let mut candidates = Vec::new();
+
+    if settings.actions.trickle_charge {
+        candidates.push(trickle_charge::probe(sysfs).await);
+    }
+
+    if settings.actions.amdgpu_panel_power {
+        candidates.push(amdgpu_panel_power::probe(sysfs).await);
+    }
+
+    let actions = candidates
+        .into_iter()
+        .filter_map(|action| match action {
+            Ok(action) => {
+                log::info!("Using action {}", action.name());
+                Some(action)
+            }
+            Err(err) => {
+                log::debug!("Skipping action: {}", err);
+                None
+            }
+        })
+        .collect();
+
+    ActionSet { actions }
+}
@@ -0,0 +1,83 @@
+use std::sync::Arc;
+
+use anyhow::Result;
+use async_trait::async_trait;
+
+use crate::sysfs::Sysfs;
+
+const POWER_SUPPLY: &str = "/sys/class/power_supply";
+
+/// Charges peripherals slowly in power-saver, which is easier on their batteries
+pub(crate) struct Action {
+    /// Power supplies of peripherals with a settable charge type
+    devices: Vec<String>,
+    sysfs: Sysfs,
+}
+
+impl Action {
+    fn charge_type(device: &str) -> String {
+        format!("{}/{}/charge_type", POWER_SUPPLY, device)
+    }
+}
+
+#[async_trait]
+impl crate::actions::Action for Action {
+    async fn activate(&self, power_profile: &crate::types::PowerProfile) -> Result<()> {
+        let charge_type = match power_profile.name.as_str() {
+            crate::holds::POWER_SAVER => "Trickle",
+            _ => "Fast",
+        };
+
+        for device in &self.devices {
+            log::debug!("Setting charge type of {} to {}", device, charge_type);
+
+            self.sysfs
+                .write(Self::charge_type(device), charge_type)
+                .await?;
+        }
+
+        Ok(())
+    }
+
+    async fn status(&self) -> Result<String> {
+        let mut statuses = Vec::new();
+
+        for device in &self.devices {
+            let charge_type = self.sysfs.read(Self::charge_type(device)).await?;
+
+            statuses.push(format!("{}: {}", device, charge_type));
+        }
+
+        Ok(statuses.join(", "))
+    }
+
+    fn name(&self) -> &str {
+        "trickle_charge"
+    }
+}
+
+pub async fn probe(sysfs: &Sysfs) -> Result<Arc<dyn crate::actions::Action + Send + Sync>> {
+    let mut devices = Vec::new();
+
+    for device in sysfs.list(POWER_SUPPLY, "").await? {
+        // The system battery is left to the firmware
+        let scope = sysfs
+            .read(format!("{}/{}/scope", POWER_SUPPLY, device))
+            .await;
+
+        if scope.is_ok_and(|scope| scope == "Device")
+            && sysfs.exists(Action::charge_type(&device)).await
+        {
+            devices.push(device);
+        }
+    }
+
+    if devices.is_empty() {
+        return Err(anyhow::anyhow!("no peripheral with a charge type"));
+    }
+
+    Ok(Arc::new(Action {
+        devices,
+        sysfs: sysfs.clone(),
+    }))
+}
@@ -0,0 +1,41 @@
+use anyhow::Result;
+
+use crate::{drivers::DriverSet, settings::Settings};
+
+/// Check every profile against the drivers probed on this machine and print the findings.
+///
+/// Returns whether every check passed.
+pub(crate) async fn run(settings: &Settings, driver_set: &DriverSet) -> Result<bool> {
+    let mut passed = true;
+
+    for (index, profile) in settings.ordered_profiles().into_iter().enumerate() {
+        if index > 0 {
+            println!();
+        }
+
+        println!("{}:", profile.name);
+
+        for (driver, findings) in driver_set.check(profile).await {
+            let findings = match findings {
+                Ok(findings) => findings,
+                Err(err) => {
+                    passed = false;
+                    println!("  FAIL  {}: unable to check: {:#}", driver, err);
+                    continue;
+                }
+            };
+
+            for finding in findings {
+                passed &= finding.ok;
+
+                match (finding.ok, finding.skipped) {
+                    (true, true) => println!("  skip  {}: {}", driver, finding.message),
+                    (true, false) => println!("  ok    {}: {}", driver, finding.message),
+                    (false, _) => println!("  FAIL  {}: {}", driver, finding.message),
+                }
+            }
+        }
+    }
+
+    Ok(passed)
+}
@@ -0,0 +1,315 @@
+use std::{
+    collections::HashMap,
+    os::unix::process::ExitStatusExt,
+    sync::atomic::{AtomicI32, Ordering},
+};
+
+use anyhow::Result;
+use clap::{Parser, Subcommand};
+use nix::sys::signal::{self, SigHandler, Signal};
+use zvariant::OwnedValue;
+
+use crate::dbus::iface::{PowerProfilesProxy, PowerrProfilesProxy};
+
+/// Name the client is installed under, upstream compatible scripts call it directly
+pub(crate) const CLIENT_NAME: &str = "powerprofilesctl";
+
+/// Command line client, compatible with upstream's powerprofilesctl
+#[derive(Parser, Debug)]
+#[command(name = CLIENT_NAME, version, about, long_about = None)]
+pub(crate) struct Cli {
+    #[command(subcommand)]
+    command: Option<Command>,
+
+    /// Talk to a daemon on the user session bus (useful for development)
+    #[arg(long, default_value_t = false, hide = true)]
+    user: bool,
+}
+
+#[derive(Subcommand, Debug)]
+pub(crate) enum Command {
+    /// List available power profiles
+    List,
+    /// Print the currently selected power profile
+    Get,
+    /// Set the currently selected power profile
+    Set {
+        /// Profile to use for set command
+        profile: String,
+    },
+    /// Launch a command while holding a power profile
+    Launch {
+        /// Profile to use for launch command
+        #[arg(short, long, default_value = crate::holds::PERFORMANCE)]
+        profile: String,
+        /// Reason to use for launch command
+        #[arg(short, long)]
+        reason: Option<String>,
+        /// AppId to use for launch command
+        #[arg(short = 'i', long)]
+        appid: Option<String>,
+        /// Command to launch
+        #[arg(trailing_var_arg = true, allow_hyphen_values = true, required = true)]
+        arguments: Vec<String>,
+    },
+    /// List currently active holds
+    ListHolds,
+    /// Print version information and exit
+    Version,
+    /// Print the daemon state, including holds, drivers and cpufreq policies
+    Status {
+        /// Print JSON instead of text, for scripts
+        #[arg(long, default_value_t = false)]
+        json: bool,
+    },
+}
+
+/// The launched child, signals sent to the client are forwarded to it
+static CHILD: AtomicI32 = AtomicI32::new(0);
+
+/// Signals upstream forwards to the launched command
+const FORWARDED_SIGNALS: [Signal; 3] = [Signal::SIGTERM, Signal::SIGINT, Signal::SIGABRT];
+
+impl Cli {
+    pub async fn run(self) -> Result<()> {
+        run(self.command.unwrap_or(Command::List), self.user).await
+    }
+}
+
+/// Run a client command, exiting the way upstream does on errors
+pub(crate) async fn run(command: Command, user: bool) -> Result<()> {
+    if let Err(err) = execute(command, user).await {
+        eprintln!("Failed to communicate with power-profiles-daemon: {}", err);
+        std::process::exit(1);
+    }
+
+    Ok(())
+}
+
+async fn execute(command: Command, user: bool) -> zbus::Result<()> {
+    match command {
+        Command::List => list(&connect(user).await?).await,
+        Command::Get => get(&connect(user).await?).await,
+        Command::Set { profile } => connect(user).await?.set_active_profile(&profile).await,
+        Command::Launch {
+            profile,
+            reason,
+            appid,
+            arguments,
+        } => launch(&connect(user).await?, profile, reason, appid, arguments).await,
+        Command::ListHolds => list_holds(&connect(user).await?).await,
+        Command::Version => {
+            version(user).await;
+            Ok(())
+        }
+        Command::Status { json } => status(user, json).await,
+    }
+}
+
+async fn connection(user: bool) -> zbus::Result<zbus::Connection> {
+    match user {
+        true => zbus::Connection::session().await,
+        false => zbus::Connection::system().await,
+    }
+}
+
+async fn connect(user: bool) -> zbus::Result<PowerProfilesProxy<'static>> {
+    PowerProfilesProxy::new(&connection(user).await?).await
+}
+
+/// A string entry of a D-Bus dictionary, empty if missing
+fn entry(dict: &HashMap<String, OwnedValue>, key: &str) -> String {
+    dict.get(key)
+        .and_then(|value| <&str>::try_from(&**value).ok())
+        .unwrap_or_default()
+        .to_owned()
+}
+
+async fn list(proxy: &PowerProfilesProxy<'_>) -> zbus::Result<()> {
+    let profiles = proxy.profiles().await?;
+    let reason = proxy.performance_degraded().await?;
+    let active = proxy.active_profile().await?;
+
+    for (index, profile) in profiles.iter().rev().enumerate() {
+        if index > 0 {
+            println!();
+        }
+
+        let name = entry(profile, "Profile");
+        let marker = if name == active { "*" } else { " " };
+
+        println!("{} {}:", marker, name);
+
+        for driver in ["CpuDriver", "PlatformDriver"] {
+            let value = entry(profile, driver);
+
+            // Upstream leaves out drivers that aren't in use
+            if !value.is_empty() {
+                println!("    {}:\t{}", driver, value);
+            }
+        }
+
+        if name == crate::holds::PERFORMANCE {
+            match reason.is_empty() {
+                true => println!("    Degraded:   no"),
+                false => println!("    Degraded:   yes ({})", reason),
+            }
+        }
+    }
+
+    Ok(())
+}
+
+async fn get(proxy: &PowerProfilesProxy<'_>) -> zbus::Result<()> {
+    println!("{}", proxy.active_profile().await?);
+
+    Ok(())
+}
+
+async fn list_holds(proxy: &PowerProfilesProxy<'_>) -> zbus::Result<()> {
+    for (index, hold) in proxy.active_profile_holds().await?.iter().enumerate() {
+        if index > 0 {
+            println!();
+        }
+
+        println!("Hold:");
+        println!("  Profile:         {}", entry(hold, "Profile"));
+        println!("  Application ID:  {}", entry(hold, "ApplicationId"));
+        println!("  Reason:          {}", entry(hold, "Reason"));
+    }
+
+    Ok(())
+}
+
+async fn version(user: bool) {
+    let daemon = match connect(user).await {
+        Ok(proxy) => proxy.version().await.ok(),
+        Err(..) => None,
+    };
+
+    println!("client: {}", env!("CARGO_PKG_VERSION"));
+    println!("daemon: {}", daemon.as_deref().unwrap_or("unknown"));
+}
+
+async fn status(user: bool, json: bool) -> zbus::Result<()> {
+    let status = PowerrProfilesProxy::new(&connection(user).await?)
+        .await?
+        .status()
+        .await?;
+
+    if json {
+        match serde_json::to_string_pretty(&status) {
+            Ok(json) => println!("{}", json),
+            Err(err) => return Err(zbus::Error::Failure(err.to_string())),
+        }
+
+        return Ok(());
+    }
+
+    println!("Active profile: {}", status.active_profile);
+
+    match status.performance_degraded.is_empty() {
+        true => println!("Degraded:       no"),
+        false => println!(
+            "Degraded:       yes ({})",
+            status.performance_degraded.join(",")
+        ),
+    }
+
+    println!();
+    println!("Drivers:");
+
+    for driver in &status.drivers {
+        println!("  {}: {}", driver.category, driver.name);
+    }
+
+    if !status.holds.is_empty() {
+        println!();
+        println!("Holds:");
+
+        for hold in &status.holds {
+            println!(
+                "  {}: {} for {} ({})",
+                hold.cookie, hold.profile, hold.application_id, hold.reason
+            );
+        }
+    }
+
+    for policy in &status.policies {
+        println!();
+        println!("policy{}:", policy.id);
+
+        for (attribute, value) in &policy.values {
+            println!("  {}: {}", attribute, value);
+        }
+    }
+
+    Ok(())
+}
+
+extern "C" fn forward_signal(signum: nix::libc::c_int) {
+    let child = CHILD.load(Ordering::SeqCst);
+
+    if child > 0 {
+        // SAFETY: kill is async-signal-safe
+        unsafe {
+            nix::libc::kill(child, signum);
+        }
+    }
+}
+
+/// Hold a profile for as long as the command runs, then exit with its status
+async fn launch(
+    proxy: &PowerProfilesProxy<'_>,
+    profile: String,
+    reason: Option<String>,
+    appid: Option<String>,
+    arguments: Vec<String>,
+) -> zbus::Result<()> {
+    let appid = appid.unwrap_or_else(|| arguments[0].clone());
+    let reason = reason.unwrap_or_else(|| format!("Running {}", appid));
+
+    let cookie = proxy.hold_profile(&profile, &reason, &appid).await?;
+
+    for forwarded in FORWARDED_SIGNALS {
+        // SAFETY: the handler only touches an atomic and calls kill
+        if let Err(err) = unsafe { signal::signal(forwarded, SigHandler::Handler(forward_signal)) }
+        {
+            log::warn!("Unable to forward {}: {}", forwarded, err);
+        }
+    }
+
+    let status = match std::process::Command::new(&arguments[0])
+        .args(&arguments[1..])
+        .spawn()
+    {
+        Ok(mut child) => {
+            CHILD.store(child.id() as i32, Ordering::SeqCst);
+            async_std::task::spawn_blocking(move || child.wait()).await
+        }
+        Err(err) => Err(err),
+    };
+
+    // The daemon may already have dropped the hold, e.g. on a profile change
+    // or a restart, which must not hide the command's own status
+    if let Err(err) = proxy.release_profile(cookie).await {
+        log::warn!("Unable to release the profile hold: {}", err);
+    }
+
+    let code = match status {
+        // Like Python's returncode, a signal is reported as its negated number
+        Ok(status) => status
+            .code()
+            .unwrap_or_else(|| -status.signal().unwrap_or_default()),
+        Err(err) => {
+            eprintln!("Failed to launch {}: {}", arguments[0], err);
+            1
+        }
+    };
+
+    if code != 0 {
+        std::process::exit(code);
+    }
+
+    Ok(())
+}
@@ -0,0 +1,106 @@
+use zbus::interface;
+
+use super::Relay;
+use crate::{
+    engine::{Engine, Event},
+    types::Status,
+};
+
+/// Daemon specific diagnostics, served next to the upstream compatible interfaces
+#[derive(Clone)]
+pub(crate) struct Handler {
+    engine: Engine,
+}
+
+impl Handler {
+    pub fn new(engine: Engine) -> Self {
+        Self { engine }
+    }
+}
+
+impl Relay for Handler {
+    fn changed_properties(event: &Event) -> &'static [&'static str] {
+        match event {
+            Event::Plan => &["Plan"],
+            Event::Mismatches => &["Mismatches"],
+            Event::ActiveProfile | Event::Actions => &["ActionStatus"],
+            _ => &[],
+        }
+    }
+}
+
+#[interface(name = "io.github.craigcabrey.PowerrProfiles")]
+impl Handler {
+    /// Everything a client needs to describe the daemon's state, in a single call
+    async fn status(&self) -> anyhow::Result<Status, zbus::fdo::Error> {
+        log::debug!("Status being requested!");
+
+        self.engine
+            .status()
+            .await
+            .map_err(|err| zbus::fdo::Error::Failed(format!("{:#}", err)))
+    }
+
+    /// Load the config file again, like sending SIGHUP but reporting what went wrong
+    async fn reload(&self) -> anyhow::Result<(), zbus::fdo::Error> {
+        log::info!("Reload being requested!");
+
+        self.engine
+            .reload()
+            .await
+            .map_err(|err| zbus::fdo::Error::Failed(format!("{:#}", err)))
+    }
+
+    /// The (name, status) of every action in use, e.g. the charge type set by trickle_charge
+    #[zbus(property)]
+    async fn action_status(&self) -> Vec<(String, String)> {
+        log::debug!("Action status being requested!");
+
+        self.engine.action_set().status().await
+    }
+
+    /// Whether writes are only planned instead of carried out
+    #[zbus(property)]
+    async fn dry_run(&self) -> bool {
+        self.engine.dry_run()
+    }
+
+    /// The (path, old value, new value) writes of the last profile activation.
+    ///
+    /// The old value is empty if it could not be read.
+    #[zbus(property)]
+    async fn plan(&self) -> Vec<(String, String, String)> {
+        log::debug!("Plan being requested!");
+
+        self.engine
+            .plan()
+            .into_iter()
+            .map(|write| {
+                (
+                    write.path.display().to_string(),
+                    write.old.unwrap_or_default(),
+                    write.new,
+                )
+            })
+            .collect()
+    }
+
+    /// The (path, expected, actual) attributes that did not take the value written by the
+    /// last profile activation
+    #[zbus(property)]
+    async fn mismatches(&self) -> Vec<(String, String, String)> {
+        log::debug!("Mismatches being requested!");
+
+        self.engine
+            .mismatches()
+            .into_iter()
+            .map(|mismatch| {
+                (
+                    mismatch.path.display().to_string(),
+                    mismatch.expected,
+                    mismatch.actual,
+                )
+            })
+            .collect()
+    }
+}
@@ -19,8 +19,18 @@
 //!
 //! [Writing a client proxy]: https://dbus2.github.io/zbus/client.html
 //! [D-Bus standard interfaces]: https://dbus.freedesktop.org/doc/dbus-specification.html#standard-interfaces,
+use std::collections::HashMap;
+
 use zbus::proxy;
+use zvariant::OwnedValue;
+
+use crate::types::Status;
 
+#[proxy(
+    interface = "org.freedesktop.UPower.PowerProfiles",
+    default_service = "org.freedesktop.UPower.PowerProfiles",
+    default_path = "/org/freedesktop/UPower/PowerProfiles"
+)]
 pub(crate) trait PowerProfiles {
     /// HoldProfile method
     fn hold_profile(&self, profile: &str, reason: &str, application_id: &str) -> zbus::Result<u32>;
@@ -30,7 +40,7 @@ pub(crate) trait PowerProfiles {
 
     /// ProfileReleased signal
     #[zbus(signal)]
-    fn profile_released(&self) -> zbus::Result<()>;
+    fn profile_released(&self, cookie: u32) -> zbus::Result<()>;
 
     /// Actions property
     #[zbus(property)]
@@ -44,7 +54,7 @@ pub(crate) trait PowerProfiles {
 
     /// ActiveProfileHolds property
     #[zbus(property)]
-    fn active_profile_holds(&self) -> zbus::Result<Vec<std::collections::HashMap<String, String>>>;
+    fn active_profile_holds(&self) -> zbus::Result<Vec<HashMap<String, OwnedValue>>>;
 
     /// PerformanceDegraded property
     #[zbus(property)]
@@ -56,9 +66,19 @@ pub(crate) trait PowerProfiles {
 
     /// Profiles property
     #[zbus(property)]
-    fn profiles(&self) -> zbus::Result<Vec<std::collections::HashMap<String, String>>>;
+    fn profiles(&self) -> zbus::Result<Vec<HashMap<String, OwnedValue>>>;
 
     /// Version property
     #[zbus(property)]
     fn version(&self) -> zbus::Result<String>;
 }
+
+#[proxy(
+    interface = "io.github.craigcabrey.PowerrProfiles",
+    default_service = "org.freedesktop.UPower.PowerProfiles",
+    default_path = "/org/freedesktop/UPower/PowerProfiles"
+)]
+pub(crate) trait PowerrProfiles {
+    /// Status method
+    fn status(&self) -> zbus::Result<Status>;
+}
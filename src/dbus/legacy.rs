@@ -1,23 +1,31 @@
-use std::collections::HashMap;
+use async_trait::async_trait;
+use zbus::{interface, message::Header, SignalContext};
 
-use zbus::interface;
-
-use crate::{drivers, settings::Settings, types::PowerProfileHold};
+use super::{hold_and_watch, power_profiles_properties, Relay};
+use crate::{
+    drivers::Category,
+    engine::{Engine, Event},
+};
 
 #[derive(Clone)]
 pub(crate) struct Handler {
-    driver_set: drivers::DriverSet,
-    profile_holds: HashMap<u32, PowerProfileHold>,
-    settings: Settings,
+    engine: Engine,
 }
 
 impl Handler {
-    pub fn new(driver_set: drivers::DriverSet, settings: Settings) -> Self {
-        Self {
-            driver_set: driver_set,
-            profile_holds: HashMap::new(),
-            settings,
-        }
+    pub fn new(engine: Engine) -> Self {
+        Self { engine }
+    }
+}
+
+#[async_trait]
+impl Relay for Handler {
+    fn changed_properties(event: &Event) -> &'static [&'static str] {
+        power_profiles_properties(event)
+    }
+
+    async fn released(ctxt: &SignalContext<'_>, cookie: u32) -> zbus::Result<()> {
+        Self::profile_released(ctxt, cookie).await
     }
 }
 
@@ -26,61 +34,47 @@ impl Handler {
     #[zbus(property)]
     async fn actions(&self) -> anyhow::Result<Vec<String>, zbus::fdo::Error> {
         log::debug!("Actions being requested!");
-        Ok(vec![])
+        Ok(self.engine.actions().await)
     }
 
     #[zbus(property)]
     async fn active_profile(&self) -> anyhow::Result<String, zbus::fdo::Error> {
         log::debug!("Active profile being requested!");
 
-        match self.driver_set.cpu.current().await {
-            Ok(profile) => match self.settings.profile_by_inferred(profile) {
-                Some(profile) => {
-                    log::debug!("Returning active profile: {}", profile.name);
-                    Ok(profile.name)
-                }
-                None => {
-                    log::warn!("Unable to determine current profile");
-                    Ok(self.settings.default.clone())
-                }
-            },
-            Err(err) => Err(zbus::fdo::Error::Failed(format!("{:?}", err)))?,
-        }
+        let profile = self.engine.active_profile().await?;
+        log::debug!("Returning active profile: {}", profile);
+
+        Ok(profile)
     }
 
     #[zbus(property)]
     async fn set_active_profile(&mut self, name: String) -> anyhow::Result<(), zbus::fdo::Error> {
         log::info!("Request to activate profile {}", name);
 
-        match self.settings.profile_by_name(&name) {
-            Some(profile) => match self.driver_set.activate(&profile).await {
-                Ok(()) => Ok(()),
-                Err(err) => Err(zbus::fdo::Error::Failed(format!("{:?}", err))),
-            },
-            None => {
-                log::warn!("Received request to activate missing profile {}", name);
-
-                Err(zbus::fdo::Error::InvalidArgs(format!("No such profile")))
-            }
-        }
+        self.engine.set_active_profile(name).await
     }
 
     #[zbus(property)]
-    async fn active_profile_holds(&self) -> Vec<PowerProfileHold> {
+    async fn active_profile_holds(&self) -> Vec<crate::dbus::types::PowerProfileHold> {
         log::debug!("Active profile holds being requested!");
 
-        self.profile_holds.clone().into_values().collect()
+        self.engine
+            .profile_holds()
+            .await
+            .into_iter()
+            .map(|hold| hold.into())
+            .collect()
     }
 
     #[zbus(property)]
-    async fn performance_degraded(&self) -> &str {
+    async fn performance_degraded(&self) -> String {
         log::debug!("Performance degraded being requested!");
 
         // - "lap-detected" (the computer is sitting on the user's lap)
         // - "high-operating-temperature" (the computer is close to overheating)
         // - "" (the empty string, if not performance is not degraded)
 
-        ""
+        self.engine.performance_degraded().await
     }
 
     #[zbus(property)]
@@ -98,14 +92,15 @@ impl Handler {
         log::debug!("Profiles being requested!");
 
         Ok(self
-            .settings
-            .profiles()
-            .clone()
-            .into_values()
+            .engine
+            .settings()
+            .ordered_profiles()
+            .into_iter()
             .map(|profile| {
                 crate::dbus::types::PowerProfile::new(
-                    &profile,
-                    self.driver_set.cpu.name().to_string(),
+                    profile,
+                    self.engine.driver_set().names(Category::Cpu),
+                    self.engine.driver_set().names(Category::Platform),
                 )
             })
             .collect())
@@ -119,13 +114,15 @@ impl Handler {
     }
 
     #[zbus(signal)]
-    async fn profile_released(ctxt: &zbus::SignalContext<'_>) -> zbus::Result<()>;
+    async fn profile_released(ctxt: &SignalContext<'_>, cookie: u32) -> zbus::Result<()>;
 
     async fn hold_profile(
-        &mut self,
+        &self,
         profile: &str,
         reason: &str,
         application_id: &str,
+        #[zbus(header)] header: Header<'_>,
+        #[zbus(connection)] connection: &zbus::Connection,
     ) -> anyhow::Result<u32, zbus::fdo::Error> {
         log::debug!(
             "Hold profile being called: profile={}, reason={}, application_id={}",
@@ -134,37 +131,20 @@ impl Handler {
             application_id
         );
 
-        let cookie = 0;
-
-        self.profile_holds.insert(
-            cookie,
-            PowerProfileHold::new(
-                application_id.to_owned(),
-                profile.to_owned(),
-                reason.to_owned(),
-            ),
-        );
-
-        match self.settings.profiles().get(profile) {
-            Some(profile) => match self.driver_set.activate(profile).await {
-                Ok(()) => Ok(cookie),
-                Err(err) => Err(zbus::fdo::Error::Failed(err.to_string())),
-            },
-            _ => Err(zbus::fdo::Error::InvalidArgs("No such profile".to_string())),
-        }
+        hold_and_watch(
+            &self.engine,
+            profile,
+            reason,
+            application_id,
+            &header,
+            connection,
+        )
+        .await
     }
 
-    fn release_profile(&mut self, cookie: u32) -> anyhow::Result<(), zbus::fdo::Error> {
+    async fn release_profile(&self, cookie: u32) -> anyhow::Result<(), zbus::fdo::Error> {
         log::debug!("Release profile being called: cookie={}", cookie);
 
-        match self.profile_holds.remove(&cookie) {
-            Some(_) => log::info!("Removed profile hold for cookie {}", cookie),
-            None => log::info!(
-                "Request to remove profile hold for missing cookie {}",
-                cookie
-            ),
-        };
-
-        Ok(())
+        self.engine.release_profile(cookie).await
     }
 }
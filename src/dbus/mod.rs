@@ -1,27 +1,193 @@
 use std::collections::HashMap;
 
-use zbus::interface;
+use async_std::channel::Receiver;
+use async_trait::async_trait;
+use futures::StreamExt;
+use zbus::{
+    fdo::Properties, interface, message::Header, object_server::InterfaceRef, Interface,
+    SignalContext,
+};
+use zvariant::Value;
 
-use crate::{drivers, settings::Settings, types::PowerProfileHold};
+use crate::{
+    drivers::Category,
+    engine::{Engine, Event},
+};
 
+pub(crate) mod extension;
+pub(crate) mod iface;
 pub(crate) mod legacy;
 mod types;
 
 #[derive(Clone)]
 pub(crate) struct Handler {
-    driver_set: drivers::DriverSet,
-    profile_holds: HashMap<u32, PowerProfileHold>,
-    settings: Settings,
+    engine: Engine,
 }
 
 impl Handler {
-    pub fn new(driver_set: drivers::DriverSet, settings: Settings) -> Self {
-        Self {
-            driver_set: driver_set,
-            profile_holds: HashMap::new(),
-            settings,
+    pub fn new(engine: Engine) -> Self {
+        Self { engine }
+    }
+}
+
+#[async_trait]
+impl Relay for Handler {
+    fn changed_properties(event: &Event) -> &'static [&'static str] {
+        power_profiles_properties(event)
+    }
+
+    async fn released(ctxt: &SignalContext<'_>, cookie: u32) -> zbus::Result<()> {
+        Self::profile_released(ctxt, cookie).await
+    }
+}
+
+/// An interface relaying engine events to its clients
+#[async_trait]
+pub(crate) trait Relay: Interface {
+    /// Properties of this interface an event changes
+    fn changed_properties(event: &Event) -> &'static [&'static str];
+
+    /// Tell the peer that took a hold it was released, if this interface has the signal
+    async fn released(_ctxt: &SignalContext<'_>, _cookie: u32) -> zbus::Result<()> {
+        Ok(())
+    }
+}
+
+/// Properties an event changes on the upstream and legacy interfaces, which are the same
+pub(crate) fn power_profiles_properties(event: &Event) -> &'static [&'static str] {
+    match event {
+        Event::ActiveProfile => &["ActiveProfile"],
+        Event::ProfileHolds => &["ActiveProfileHolds"],
+        Event::Profiles => &["Profiles"],
+        Event::Actions => &["Actions"],
+        Event::PerformanceDegraded => &["PerformanceDegraded"],
+        Event::ProfileReleased { .. } | Event::Plan | Event::Mismatches => &[],
+    }
+}
+
+/// Relay engine events to the clients of an interface
+pub(crate) async fn forward_events<I: Relay>(iface: InterfaceRef<I>, events: Receiver<Event>) {
+    while let Ok(event) = events.recv().await {
+        let ctxt = iface.signal_context();
+
+        let result = match event {
+            Event::ProfileReleased { cookie, requester } => {
+                match requester_context(ctxt, &requester) {
+                    Ok(ctxt) => I::released(&ctxt, cookie).await,
+                    Err(err) => Err(err),
+                }
+            }
+            event => properties_changed(&iface, I::changed_properties(&event)).await,
+        };
+
+        if let Err(err) = result {
+            log::warn!("Failed to emit signal on {}: {}", ctxt.path(), err);
+        }
+    }
+}
+
+/// Emit PropertiesChanged with the current values of some properties of an interface
+async fn properties_changed<I: Interface>(
+    iface: &InterfaceRef<I>,
+    names: &[&str],
+) -> zbus::Result<()> {
+    if names.is_empty() {
+        return Ok(());
+    }
+
+    let mut values = Vec::new();
+
+    for name in names {
+        match Interface::get(&*iface.get().await, name).await {
+            Some(Ok(value)) => values.push((*name, value)),
+            Some(Err(err)) => return Err(err.into()),
+            None => return Err(zbus::Error::Failure(format!("No property {}", name))),
+        }
+    }
+
+    let changed: HashMap<&str, &Value<'_>> = values
+        .iter()
+        .map(|(name, value)| (*name, &**value))
+        .collect();
+
+    Properties::properties_changed(iface.signal_context(), I::name(), &changed, &[]).await
+}
+
+/// Take a hold for the sender of a call, released once the sender leaves the bus
+pub(crate) async fn hold_and_watch(
+    engine: &Engine,
+    profile: &str,
+    reason: &str,
+    application_id: &str,
+    header: &Header<'_>,
+    connection: &zbus::Connection,
+) -> anyhow::Result<u32, zbus::fdo::Error> {
+    let requester = header
+        .sender()
+        .map(|sender| sender.to_string())
+        .unwrap_or_default();
+
+    let cookie = engine
+        .hold_profile(profile, reason, application_id, &requester)
+        .await?;
+
+    if engine.track_requester(&requester).await {
+        let connection = connection.clone();
+        let engine = engine.clone();
+
+        async_std::task::spawn(async move {
+            match requester_vanished(&connection, &requester).await {
+                Ok(()) => engine.release_requester(&requester).await,
+                Err(err) => {
+                    log::warn!("Unable to watch {} for disconnects: {}", requester, err);
+                    engine.untrack_requester(&requester).await;
+                }
+            }
+        });
+    }
+
+    Ok(cookie)
+}
+
+/// Hold signals are only sent to the peer that took the hold, like upstream
+pub(crate) fn requester_context(
+    ctxt: &SignalContext<'_>,
+    requester: &str,
+) -> zbus::Result<SignalContext<'static>> {
+    if requester.is_empty() {
+        return Ok(ctxt.to_owned());
+    }
+
+    Ok(ctxt
+        .to_owned()
+        .set_destination(zbus::names::BusName::try_from(requester.to_owned())?))
+}
+
+/// Resolves once `requester` no longer owns its unique name on the bus
+pub(crate) async fn requester_vanished(
+    connection: &zbus::Connection,
+    requester: &str,
+) -> zbus::Result<()> {
+    let proxy = zbus::fdo::DBusProxy::new(connection).await?;
+    let mut changes = proxy
+        .receive_name_owner_changed_with_args(&[(0, requester)])
+        .await?;
+
+    // The peer may already be gone by the time the match rule is in place
+    if !proxy
+        .name_has_owner(zbus::names::BusName::try_from(requester)?)
+        .await?
+    {
+        return Ok(());
+    }
+
+    while let Some(change) = changes.next().await {
+        if change.args()?.new_owner().is_none() {
+            break;
         }
     }
+
+    Ok(())
 }
 
 #[interface(name = "org.freedesktop.UPower.PowerProfiles")]
@@ -29,61 +195,47 @@ impl Handler {
     #[zbus(property)]
     async fn actions(&self) -> anyhow::Result<Vec<String>, zbus::fdo::Error> {
         log::debug!("Actions being requested!");
-        Ok(vec![])
+        Ok(self.engine.actions().await)
     }
 
     #[zbus(property)]
     async fn active_profile(&self) -> anyhow::Result<String, zbus::fdo::Error> {
         log::debug!("Active profile being requested!");
 
-        match self.driver_set.cpu.current().await {
-            Ok(profile) => match self.settings.profile_by_inferred(profile) {
-                Some(profile) => {
-                    log::debug!("Returning active profile: {}", profile.name);
-                    Ok(profile.name)
-                }
-                None => {
-                    log::warn!("Unable to determine current profile");
-                    Ok(self.settings.default.clone())
-                }
-            },
-            Err(err) => Err(zbus::fdo::Error::Failed(format!("{:?}", err)))?,
-        }
+        let profile = self.engine.active_profile().await?;
+        log::debug!("Returning active profile: {}", profile);
+
+        Ok(profile)
     }
 
     #[zbus(property)]
     async fn set_active_profile(&mut self, name: String) -> anyhow::Result<(), zbus::fdo::Error> {
         log::info!("Request to activate profile {}", name);
 
-        match self.settings.profile_by_name(&name) {
-            Some(profile) => match self.driver_set.activate(profile).await {
-                Ok(()) => Ok(()),
-                Err(err) => Err(zbus::fdo::Error::Failed(format!("{:?}", err))),
-            },
-            None => {
-                log::warn!("Received request to activate missing profile {}", name);
-
-                Err(zbus::fdo::Error::InvalidArgs(format!("No such profile")))
-            }
-        }
+        self.engine.set_active_profile(name).await
     }
 
     #[zbus(property)]
-    async fn active_profile_holds(&self) -> Vec<PowerProfileHold> {
+    async fn active_profile_holds(&self) -> Vec<types::PowerProfileHold> {
         log::debug!("Active profile holds being requested!");
 
-        self.profile_holds.clone().into_values().collect()
+        self.engine
+            .profile_holds()
+            .await
+            .into_iter()
+            .map(|hold| hold.into())
+            .collect()
     }
 
     #[zbus(property)]
-    async fn performance_degraded(&self) -> &str {
+    async fn performance_degraded(&self) -> String {
         log::debug!("Performance degraded being requested!");
 
         // - "lap-detected" (the computer is sitting on the user's lap)
         // - "high-operating-temperature" (the computer is close to overheating)
         // - "" (the empty string, if not performance is not degraded)
 
-        ""
+        self.engine.performance_degraded().await
     }
 
     #[zbus(property)]
@@ -99,12 +251,16 @@ impl Handler {
         log::debug!("Profiles being requested!");
 
         Ok(self
-            .settings
-            .profiles()
-            .clone()
-            .into_values()
+            .engine
+            .settings()
+            .ordered_profiles()
+            .into_iter()
             .map(|profile| {
-                types::PowerProfile::new(&profile, self.driver_set.cpu.name().to_string())
+                types::PowerProfile::new(
+                    profile,
+                    self.engine.driver_set().names(Category::Cpu),
+                    self.engine.driver_set().names(Category::Platform),
+                )
             })
             .collect())
     }
@@ -117,13 +273,15 @@ impl Handler {
     }
 
     #[zbus(signal)]
-    async fn profile_released(ctxt: &zbus::SignalContext<'_>) -> zbus::Result<()>;
+    async fn profile_released(ctxt: &SignalContext<'_>, cookie: u32) -> zbus::Result<()>;
 
     async fn hold_profile(
-        &mut self,
+        &self,
         profile: &str,
         reason: &str,
         application_id: &str,
+        #[zbus(header)] header: Header<'_>,
+        #[zbus(connection)] connection: &zbus::Connection,
     ) -> anyhow::Result<u32, zbus::fdo::Error> {
         log::debug!(
             "Hold profile being called: profile={}, reason={}, application_id={}",
@@ -132,37 +290,20 @@ impl Handler {
             application_id
         );
 
-        let cookie = 0;
-
-        self.profile_holds.insert(
-            cookie,
-            PowerProfileHold::new(
-                application_id.to_owned(),
-                profile.to_owned(),
-                reason.to_owned(),
-            ),
-        );
-
-        match self.settings.profiles().get(profile) {
-            Some(profile) => match self.driver_set.activate(profile).await {
-                Ok(()) => Ok(cookie),
-                Err(err) => Err(zbus::fdo::Error::Failed(err.to_string())),
-            },
-            _ => Err(zbus::fdo::Error::InvalidArgs("No such profile".to_string())),
-        }
+        hold_and_watch(
+            &self.engine,
+            profile,
+            reason,
+            application_id,
+            &header,
+            connection,
+        )
+        .await
     }
 
-    fn release_profile(&mut self, cookie: u32) -> anyhow::Result<(), zbus::fdo::Error> {
+    async fn release_profile(&self, cookie: u32) -> anyhow::Result<(), zbus::fdo::Error> {
         log::debug!("Release profile being called: cookie={}", cookie);
 
-        match self.profile_holds.remove(&cookie) {
-            Some(_) => log::info!("Removed profile hold for cookie {}", cookie),
-            None => log::info!(
-                "Request to remove profile hold for missing cookie {}",
-                cookie
-            ),
-        };
-
-        Ok(())
+        self.engine.release_profile(cookie).await
     }
 }
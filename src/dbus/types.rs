@@ -15,12 +15,41 @@ pub(crate) struct PowerProfile {
 }
 
 impl PowerProfile {
-    pub(crate) fn new(power_profile: &types::PowerProfile, driver: String) -> Self {
+    pub(crate) fn new(
+        power_profile: &types::PowerProfile,
+        cpu_driver: String,
+        platform_driver: String,
+    ) -> Self {
+        // Like upstream, "multiple" is only reported when more than one kind of driver is in use
+        let driver = match (cpu_driver.is_empty(), platform_driver.is_empty()) {
+            (false, false) => "multiple".to_string(),
+            (true, _) => platform_driver.clone(),
+            (_, true) => cpu_driver.clone(),
+        };
+
         Self {
             Profile: power_profile.name.clone(),
-            CpuDriver: driver,
-            PlatformDriver: "placeholder".to_string(),
-            Driver: "multiple".to_string(),
+            CpuDriver: cpu_driver,
+            PlatformDriver: platform_driver,
+            Driver: driver,
+        }
+    }
+}
+
+#[derive(Clone, Debug, SerializeDict, Type, zvariant::Value, zvariant::OwnedValue)]
+#[zvariant(signature = "a{sv}", rename_all = "PascalCase")]
+pub(crate) struct PowerProfileHold {
+    ApplicationId: String,
+    Profile: String,
+    Reason: String,
+}
+
+impl From<types::PowerProfileHold> for PowerProfileHold {
+    fn from(hold: types::PowerProfileHold) -> Self {
+        Self {
+            ApplicationId: hold.application_id,
+            Profile: hold.profile,
+            Reason: hold.reason,
         }
     }
 }
@@ -3,12 +3,14 @@ use std::{collections::HashMap, sync::Arc};
 use anyhow::Result;
 
 use super::types::PowerProfile;
+use crate::sysfs::Sysfs;
 
 mod pstate;
 
 const SCALING_DRIVER_PATH: &str = "/sys/devices/system/cpu/cpufreq/policy0/scaling_driver";
 
 pub async fn probe(
+    sysfs: &Sysfs,
     profiles: &Vec<PowerProfile>,
 ) -> Result<Arc<dyn crate::drivers::Driver + Send + Sync>> {
     let profile_driver_settings: HashMap<String, pstate::DriverSettings> = profiles
@@ -27,16 +29,25 @@ pub async fn probe(
         })
         .collect();
 
-    let driver = std::fs::read_to_string(SCALING_DRIVER_PATH)?;
+    let driver = sysfs.read(SCALING_DRIVER_PATH).await?;
 
-    match driver.trim() {
+    match driver.as_str() {
         "amd-pstate" => Ok(Arc::new(
-            pstate::Driver::new(false, "amd-pstate".to_string(), profile_driver_settings).await?,
+            pstate::Driver::new(
+                "amd-pstate".to_string(),
+                profile_driver_settings,
+                sysfs.clone(),
+            )
+            .await?,
         )),
         "amd-pstate-epp" => Ok(Arc::new(
-            pstate::Driver::new(false, "amd-pstate-epp".to_string(), profile_driver_settings)
-                .await?,
+            pstate::Driver::new(
+                "amd-pstate-epp".to_string(),
+                profile_driver_settings,
+                sysfs.clone(),
+            )
+            .await?,
         )),
-        _ => Err(anyhow::anyhow!("unsupported driver {}", driver.trim())),
+        _ => Err(anyhow::anyhow!("unsupported driver {}", driver)),
     }
 }
@@ -1,20 +1,19 @@
-use anyhow::{Context, Result};
-use async_std::fs;
+use anyhow::Result;
 use async_trait::async_trait;
 use serde::Deserialize;
 use std::{collections::HashMap, str::FromStr};
 
 use super::super::types::{EnergyPreference, ScalingGovernor};
-use crate::drivers::cpu::utils;
+use crate::{drivers::cpu::utils, sysfs::Sysfs};
 
 #[derive(Deserialize)]
 pub(crate) struct DriverSettings {}
 
 pub(crate) struct Driver {
-    dry_run: bool,
     name: String,
     status: Status,
     _profile_driver_settings: HashMap<String, DriverSettings>,
+    sysfs: Sysfs,
 }
 
 impl Driver {
@@ -25,84 +24,115 @@ impl Driver {
         "/sys/devices/system/cpu/cpufreq/policy0/scaling_governor";
 
     pub async fn new(
-        dry_run: bool,
         name: String,
         profile_driver_settings: HashMap<String, DriverSettings>,
+        sysfs: Sysfs,
     ) -> Result<Self> {
         Ok(Self {
-            dry_run: dry_run,
             name: name,
-            status: Status::current().await?,
+            status: Status::current(&sysfs).await?,
             _profile_driver_settings: profile_driver_settings,
+            sysfs,
         })
     }
 
     async fn boost_enabled(&self) -> Result<bool> {
-        match fs::read_to_string(Self::BOOST_FLAG)
-            .await
-            .with_context(|| format!("Failed to read from {}", Self::BOOST_FLAG))
-        {
-            Ok(res) => Ok(res.trim().parse()?),
+        match self.sysfs.read(Self::BOOST_FLAG).await {
+            Ok(res) => Ok(res.parse()?),
             Err(..) => Ok(true),
         }
     }
 
     async fn energy_preference(&self) -> Result<EnergyPreference> {
-        Ok(fs::read_to_string(Self::ENERGY_PREFERENCE)
+        Ok(self
+            .sysfs
+            .read(Self::ENERGY_PREFERENCE)
             .await?
-            .trim()
+            .as_str()
             .try_into()?)
     }
 
     async fn scaling_governor(&self) -> Result<ScalingGovernor> {
-        Ok(fs::read_to_string(Self::SCALING_GOVERNOR)
+        Ok(self
+            .sysfs
+            .read(Self::SCALING_GOVERNOR)
             .await?
-            .trim()
+            .as_str()
             .try_into()?)
     }
 }
 
 #[async_trait]
 impl crate::drivers::Driver for Driver {
-    // TODO: Figure out a way to make this atomic
-    async fn activate(&self, power_profile: &super::super::types::PowerProfile) -> Result<()> {
-        log::debug!("Activating profile {:?}", power_profile);
-
-        if self.dry_run {
-            log::debug!("Would have activated power profile {:#?}", power_profile);
+    async fn activate(&self, power_profile: &crate::types::PowerProfile) -> Result<()> {
+        let power_profile = &power_profile.cpu;
 
-            return Ok(());
-        }
+        log::debug!("Activating profile {:?}", power_profile);
 
         if self.status.boost_supported() {
             log::debug!("Boost is supported!");
 
             if power_profile.boost {
-                fs::write(Self::BOOST_FLAG, "1").await?;
+                self.sysfs.write(Self::BOOST_FLAG, "1").await?;
             }
         } else {
             log::warn!("Boost specified, but the current mode does not support it!");
         }
 
-        utils::activate_maximum_frequency(power_profile.maximum_frequency).await?;
-        utils::activate_scaling_governor(power_profile.scaling_governor).await?;
-        utils::activate_energy_preference(power_profile.energy_preference).await?;
+        utils::activate_maximum_frequency(&self.sysfs, power_profile.maximum_frequency).await?;
+        utils::activate_scaling_governor(&self.sysfs, power_profile.scaling_governor).await?;
+        utils::activate_energy_preference(&self.sysfs, power_profile.energy_preference).await?;
 
         Ok(())
     }
 
     async fn current(&self) -> Result<crate::types::InferredPowerProfile> {
         Ok(crate::types::InferredPowerProfile {
-            boost: self.boost_enabled().await?,
-            scaling_governor: self.scaling_governor().await?,
-            energy_preference: self.energy_preference().await?,
-            maximum_frequency: utils::maximum_frequency().await?,
+            boost: Some(self.boost_enabled().await?),
+            scaling_governor: Some(self.scaling_governor().await?),
+            energy_preference: Some(self.energy_preference().await?),
+            maximum_frequency: Some(utils::maximum_frequency(&self.sysfs).await?),
+            frequency_range: Some(utils::frequency_range(&self.sysfs).await?),
+            ..Default::default()
         })
     }
 
+    async fn check(
+        &self,
+        power_profile: &crate::types::PowerProfile,
+    ) -> Result<Vec<crate::drivers::Finding>> {
+        let mut findings = utils::check_profile(&self.sysfs, &power_profile.cpu).await?;
+
+        if power_profile.cpu.boost {
+            findings.push(match self.status.boost_supported() {
+                true => crate::drivers::Finding::new(
+                    true,
+                    format!(
+                        "boost is controllable in amd-pstate {} mode",
+                        self.status.as_str()
+                    ),
+                ),
+                false => crate::drivers::Finding::skipped(format!(
+                    "boost is not controllable in amd-pstate {} mode",
+                    self.status.as_str()
+                )),
+            });
+        }
+
+        Ok(findings)
+    }
+
+    fn category(&self) -> crate::drivers::Category {
+        crate::drivers::Category::Cpu
+    }
+
     fn name(&self) -> &str {
         &self.name
     }
+
+    fn priority(&self) -> crate::drivers::Priority {
+        crate::drivers::Priority::Specific
+    }
 }
 
 enum Status {
@@ -114,8 +144,16 @@ enum Status {
 impl Status {
     const PSTATE_STATUS_PATH: &'static str = "/sys/devices/system/cpu/amd_pstate/status";
 
-    async fn current() -> Result<Self> {
-        Self::from_str(&fs::read_to_string(Self::PSTATE_STATUS_PATH).await?.trim())
+    async fn current(sysfs: &Sysfs) -> Result<Self> {
+        Self::from_str(&sysfs.read(Self::PSTATE_STATUS_PATH).await?)
+    }
+
+    fn as_str(&self) -> &str {
+        match self {
+            Self::Active => "active",
+            Self::Guided => "guided",
+            Self::Passive => "passive",
+        }
     }
 
     fn boost_supported(&self) -> bool {
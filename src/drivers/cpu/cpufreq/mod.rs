@@ -1,163 +1,256 @@
-#![allow(unused)]
-
 use std::sync::Arc;
 
 use anyhow::Result;
-use async_std::fs;
 use async_trait::async_trait;
 
-use super::super::cpu::types::{EnergyPreference, ScalingGovernor};
+use super::super::cpu::types::ScalingGovernor;
 use super::types::PowerProfile;
-use crate::drivers::cpu::utils;
+use crate::{drivers::cpu::utils, sysfs::Sysfs};
 
-const AFFECTED_CPUS: &'static str = "affected_cpus";
 const CPUINFO_MAX_FREQ: &'static str = "cpuinfo_max_freq";
 const CPUINFO_MIN_FREQ: &'static str = "cpuinfo_min_freq";
-const CPUINFO_TRANSITION_LATENCY: &'static str = "cpuinfo_transition_latency";
 const ENERGY_PERFORMANCE_AVAILABLE_PREFERENCES: &'static str =
     "energy_performance_available_preferences";
 const ENERGY_PERFORMANCE_PREFERENCE: &'static str = "energy_performance_preference";
-const RELATED_CPUS: &'static str = "related_cpus";
 const SCALING_AVAILABLE_GOVERNORS: &'static str = "scaling_available_governors";
-const SCALING_DRIVER: &'static str = "scaling_driver";
-const SCALING_CUR_FREQ: &'static str = "scaling_cur_freq";
 const SCALING_GOVERNOR: &'static str = "scaling_governor";
 const SCALING_MIN_FREQ: &'static str = "scaling_min_freq";
 const SCALING_MAX_FREQ: &'static str = "scaling_max_freq";
-const SCALING_SETSPEED: &'static str = "scaling_setspeed";
+
+const BOOST_FLAG: &str = "/sys/devices/system/cpu/cpufreq/boost";
+
+// Outside of the pstate drivers the powersave governor pins the lowest frequency, so
+// dynamic governors are preferred to keep the system responsive
+const PERFORMANCE_GOVERNORS: &[&str] = &["performance", "schedutil", "ondemand"];
+const POWERSAVE_GOVERNORS: &[&str] = &["schedutil", "ondemand", "conservative", "powersave"];
 
 use crate::drivers;
 
 #[derive(Debug)]
 pub(crate) struct Driver {
-    policies: Vec<Policy>,
+    sysfs: Sysfs,
 }
 
 #[async_trait]
 impl crate::drivers::Driver for Driver {
-    async fn activate(
-        &self,
-        _power_profile: &super::super::cpu::types::PowerProfile,
-    ) -> Result<()> {
-        log::debug!("Activating!");
+    async fn activate(&self, power_profile: &crate::types::PowerProfile) -> Result<()> {
+        let power_profile = &power_profile.cpu;
+
+        log::debug!("Activating profile {:?}", power_profile);
+
+        if self.sysfs.exists(BOOST_FLAG).await {
+            self.sysfs
+                .write(BOOST_FLAG, if power_profile.boost { "1" } else { "0" })
+                .await?;
+        }
+
+        // Policies are enumerated on every activation to pick up CPUs brought online since
+        futures::future::try_join_all(
+            self.policies()
+                .await?
+                .iter()
+                .map(|policy| policy.activate(power_profile)),
+        )
+        .await?;
+
         Ok(())
     }
 
     async fn current(&self) -> Result<crate::types::InferredPowerProfile> {
+        // Every policy is set alike, so the first one speaks for all of them
+        let id = utils::online_policies(&self.sysfs)
+            .await?
+            .first()
+            .map(|policy| policy.id)
+            .ok_or_else(|| anyhow::anyhow!("No cpufreq policies"))?;
+        let read = |property| Policy::read_policy_property(&self.sysfs, id, property);
+
+        let boost = match self.sysfs.read(BOOST_FLAG).await {
+            Ok(res) => Some(res == "1"),
+            Err(..) => None,
+        };
+
+        // Only present when the scaling driver supports EPP
+        let energy_preference = match read(ENERGY_PERFORMANCE_PREFERENCE).await {
+            Ok(energy_preference) => Some(energy_preference.as_str().try_into()?),
+            Err(..) => None,
+        };
+
+        let scaling_governor = match read(SCALING_GOVERNOR).await?.as_str() {
+            "performance" => ScalingGovernor::Performance,
+            _ => ScalingGovernor::Powersave,
+        };
+
         Ok(crate::types::InferredPowerProfile {
-            boost: true,
-            energy_preference: EnergyPreference::Performance,
-            maximum_frequency: 4000000,
-            scaling_governor: ScalingGovernor::Performance,
+            boost,
+            energy_preference,
+            maximum_frequency: Some(read(SCALING_MAX_FREQ).await?.parse()?),
+            frequency_range: Some((
+                read(CPUINFO_MIN_FREQ).await?.parse()?,
+                read(CPUINFO_MAX_FREQ).await?.parse()?,
+            )),
+            scaling_governor: Some(scaling_governor),
+            ..Default::default()
         })
     }
 
+    async fn check(
+        &self,
+        power_profile: &crate::types::PowerProfile,
+    ) -> Result<Vec<drivers::Finding>> {
+        let power_profile = &power_profile.cpu;
+        let policies = self.policies().await?;
+        let energy_preference = power_profile.energy_preference.to_string();
+
+        let failing = |check: &dyn Fn(&Policy) -> bool| -> Vec<u32> {
+            policies
+                .iter()
+                .filter(|policy| !check(policy))
+                .map(|policy| policy.id)
+                .collect()
+        };
+
+        let mut findings = Vec::new();
+
+        // Activation leaves boost and the energy preference alone where they are missing
+        if power_profile.boost {
+            findings.push(match self.sysfs.exists(BOOST_FLAG).await {
+                true => drivers::Finding::new(
+                    true,
+                    format!("boost is controllable through {}", BOOST_FLAG),
+                ),
+                false => drivers::Finding::skipped(format!(
+                    "boost is not controllable, {} is missing",
+                    BOOST_FLAG
+                )),
+            });
+        }
+
+        findings.push(
+            match policies.iter().any(|policy| policy.has_energy_preference()) {
+                true => drivers::Finding::policies(
+                    format!("energy preference {} is available", energy_preference),
+                    &failing(&|policy| {
+                        !policy.has_energy_preference()
+                            || policy
+                                .energy_performance_available_preferences
+                                .contains(&energy_preference)
+                    }),
+                ),
+                false => drivers::Finding::skipped(format!(
+                    "energy preference {} is not supported by the scaling driver",
+                    energy_preference
+                )),
+            },
+        );
+
+        findings.push(drivers::Finding::policies(
+            format!(
+                "a {} scaling governor is available",
+                power_profile.scaling_governor.to_string()
+            ),
+            &failing(&|policy| {
+                policy
+                    .governor_for(power_profile.scaling_governor)
+                    .is_some()
+            }),
+        ));
+
+        if let Some(maximum_frequency) = power_profile.maximum_frequency {
+            findings.push(drivers::Finding::policies(
+                format!(
+                    "maximum frequency {} is within cpuinfo_min_freq..cpuinfo_max_freq",
+                    maximum_frequency
+                ),
+                &failing(&|policy| {
+                    (policy.cpuinfo_min_freq..=policy.cpuinfo_max_freq).contains(&maximum_frequency)
+                }),
+            ));
+        }
+
+        Ok(findings)
+    }
+
+    fn category(&self) -> crate::drivers::Category {
+        crate::drivers::Category::Cpu
+    }
+
     fn name(&self) -> &str {
         "cpufreq"
     }
 }
 
 impl Driver {
-    pub(crate) async fn from_system() -> Result<Self> {
-        Ok(Self {
-            policies: futures::future::join_all(
-                utils::online_cpu_id_iter(&utils::online_cpus().await?)?
-                    .map(|cpu_id| Policy::from_cpu_id(cpu_id)),
-            )
-            .await
-            .into_iter()
-            .collect::<Result<Vec<_>, _>>()?,
-        })
+    async fn policies(&self) -> Result<Vec<Policy>> {
+        load_policies(&self.sysfs).await
     }
 }
 
 pub async fn probe(
-    profiles: &Vec<PowerProfile>,
+    sysfs: &Sysfs,
+    _profiles: &Vec<PowerProfile>,
 ) -> Result<Arc<dyn crate::drivers::Driver + Send + Sync>> {
-    let driver = Driver::from_system().await?;
-    log::trace!("Loaded {:#?}", driver);
-    Ok(Arc::new(driver))
-}
+    let driver = Driver {
+        sysfs: sysfs.clone(),
+    };
+    let policies = driver.policies().await?;
+    log::trace!("Loaded {:#?}", policies);
 
-#[derive(Debug)]
-pub(crate) struct CPUFreq {
-    policies: Vec<Policy>,
+    if policies.is_empty() {
+        return Err(anyhow::anyhow!("No cpufreq policies found"));
+    }
+
+    Ok(Arc::new(driver))
 }
 
-impl CPUFreq {
-    pub(crate) async fn from_system() -> Result<Self> {
-        Ok(Self {
-            policies: futures::future::join_all(
-                utils::online_cpu_id_iter(&utils::online_cpus().await?)?
-                    .map(|cpu_id| Policy::from_cpu_id(cpu_id)),
-            )
-            .await
+/// Every policy governing an online CPU
+async fn load_policies(sysfs: &Sysfs) -> Result<Vec<Policy>> {
+    futures::future::join_all(
+        utils::online_policies(sysfs)
+            .await?
             .into_iter()
-            .collect::<Result<Vec<_>, _>>()?,
-        })
-    }
+            .map(|policy| Policy::from_policy_id(sysfs, policy.id)),
+    )
+    .await
+    .into_iter()
+    .collect()
 }
 
 #[derive(Debug)]
 struct Policy {
-    affected_cpus: Vec<u32>,
-    cpu_id: u32,
+    id: u32,
     cpuinfo_min_freq: u32,
     cpuinfo_max_freq: u32,
-    cpuinfo_transition_latency: u32,
     energy_performance_available_preferences: Vec<String>,
-    energy_performance_preference: String,
-    related_cpus: Vec<u32>,
     scaling_available_governors: Vec<String>,
-    scaling_driver: String,
-    scaling_cur_freq: u32,
-    scaling_governor: String,
     scaling_min_freq: u32,
-    scaling_max_freq: u32,
+    sysfs: Sysfs,
 }
 
 impl Policy {
-    pub(crate) async fn from_cpu_id(cpu_id: u32) -> Result<Self> {
+    pub(crate) async fn from_policy_id(sysfs: &Sysfs, id: u32) -> Result<Self> {
         Ok(Self {
-            affected_cpus: Self::read_policy_property(cpu_id, AFFECTED_CPUS)
-                .await?
-                .split(" ")
-                .map(|s| s.parse().unwrap())
-                .collect::<Vec<u32>>(),
-            cpu_id: cpu_id,
-            cpuinfo_max_freq: Self::read_policy_property(cpu_id, CPUINFO_MAX_FREQ)
+            id,
+            cpuinfo_max_freq: Self::read_policy_property(sysfs, id, CPUINFO_MAX_FREQ)
                 .await?
                 .parse()?,
-            cpuinfo_min_freq: Self::read_policy_property(cpu_id, CPUINFO_MIN_FREQ)
+            cpuinfo_min_freq: Self::read_policy_property(sysfs, id, CPUINFO_MIN_FREQ)
                 .await?
                 .parse()?,
-            cpuinfo_transition_latency: Self::read_policy_property(
-                cpu_id,
-                CPUINFO_TRANSITION_LATENCY,
-            )
-            .await?
-            .parse()?,
+            // Only present when the scaling driver supports EPP
             energy_performance_available_preferences: Self::read_policy_property(
-                cpu_id,
+                sysfs,
+                id,
                 ENERGY_PERFORMANCE_AVAILABLE_PREFERENCES,
             )
-            .await?
-            .split(" ")
+            .await
+            .unwrap_or_default()
+            .split_whitespace()
             .map(|item| item.to_string())
             .collect(),
-            energy_performance_preference: Self::read_policy_property(
-                cpu_id,
-                ENERGY_PERFORMANCE_PREFERENCE,
-            )
-            .await?
-            .parse()?,
-            related_cpus: Self::read_policy_property(cpu_id, RELATED_CPUS)
-                .await?
-                .split(" ")
-                .map(|s| s.parse().unwrap())
-                .collect::<Vec<u32>>(),
             scaling_available_governors: Self::read_policy_property(
-                cpu_id,
+                sysfs,
+                id,
                 SCALING_AVAILABLE_GOVERNORS,
             )
             .await?
@@ -165,42 +258,97 @@ impl Policy {
             .split(" ")
             .map(|item| item.to_string())
             .collect(),
-            scaling_driver: Self::read_policy_property(cpu_id, SCALING_DRIVER).await?,
-            scaling_cur_freq: Self::read_policy_property(cpu_id, SCALING_CUR_FREQ)
-                .await?
-                .parse()?,
-            scaling_governor: Self::read_policy_property(cpu_id, SCALING_GOVERNOR).await?,
-            scaling_min_freq: Self::read_policy_property(cpu_id, SCALING_MIN_FREQ)
-                .await?
-                .parse()?,
-            scaling_max_freq: Self::read_policy_property(cpu_id, SCALING_MAX_FREQ)
+            scaling_min_freq: Self::read_policy_property(sysfs, id, SCALING_MIN_FREQ)
                 .await?
                 .parse()?,
+            sysfs: sysfs.clone(),
         })
     }
 
-    pub(crate) async fn scaling_setspeed(&self, value: u32) -> Result<()> {
-        Self::write_policy_property(self.cpu_id, SCALING_SETSPEED, value.to_string()).await
+    fn has_energy_preference(&self) -> bool {
+        !self.energy_performance_available_preferences.is_empty()
     }
 
-    async fn read_policy_property(cpu_id: u32, property: &str) -> Result<String> {
-        Ok(fs::read_to_string(format!(
-            "/sys/devices/system/cpu/cpufreq/policy{}/{}",
-            cpu_id, property
-        ))
-        .await?
-        .trim()
-        .to_owned())
+    /// The first governor of the candidates this policy offers
+    fn governor_for(&self, scaling_governor: ScalingGovernor) -> Option<&str> {
+        let candidates = match scaling_governor {
+            ScalingGovernor::Performance => PERFORMANCE_GOVERNORS,
+            ScalingGovernor::Powersave => POWERSAVE_GOVERNORS,
+        };
+
+        candidates.iter().copied().find(|candidate| {
+            self.scaling_available_governors
+                .iter()
+                .any(|available| available == candidate)
+        })
     }
 
-    async fn write_policy_property(cpu_id: u32, property: &str, value: String) -> Result<()> {
-        Ok(fs::write(
-            format!(
-                "/sys/devices/system/cpu/cpufreq/policy{}/{}",
-                cpu_id, property
+    async fn activate(&self, power_profile: &PowerProfile) -> Result<()> {
+        // Clamp to what the hardware can do, no maximum means no limit
+        let maximum_frequency = power_profile
+            .maximum_frequency
+            .unwrap_or(self.cpuinfo_max_freq)
+            .clamp(self.cpuinfo_min_freq, self.cpuinfo_max_freq);
+
+        // The kernel refuses a maximum below the current minimum
+        if self.scaling_min_freq > maximum_frequency {
+            self.write_property(SCALING_MIN_FREQ, self.cpuinfo_min_freq.to_string())
+                .await?;
+        }
+
+        self.write_property(SCALING_MAX_FREQ, maximum_frequency.to_string())
+            .await?;
+
+        match self.governor_for(power_profile.scaling_governor) {
+            Some(governor) => {
+                self.write_property(SCALING_GOVERNOR, governor.to_string())
+                    .await?
+            }
+            None => log::warn!(
+                "No governor for {:?} on policy{}, available: {}",
+                power_profile.scaling_governor,
+                self.id,
+                self.scaling_available_governors.join(" ")
             ),
-            value,
-        )
-        .await?)
+        }
+
+        let energy_preference = power_profile.energy_preference.to_string();
+
+        if self
+            .energy_performance_available_preferences
+            .contains(&energy_preference)
+        {
+            self.write_property(ENERGY_PERFORMANCE_PREFERENCE, energy_preference)
+                .await?;
+        } else if self.has_energy_preference() {
+            log::warn!(
+                "Energy preference {} is not available on policy{}",
+                energy_preference,
+                self.id
+            );
+        }
+
+        Ok(())
+    }
+
+    async fn write_property(&self, property: &str, value: String) -> Result<()> {
+        self.sysfs
+            .write(
+                format!(
+                    "/sys/devices/system/cpu/cpufreq/policy{}/{}",
+                    self.id, property
+                ),
+                value,
+            )
+            .await
+    }
+
+    async fn read_policy_property(sysfs: &Sysfs, id: u32, property: &str) -> Result<String> {
+        sysfs
+            .read(format!(
+                "/sys/devices/system/cpu/cpufreq/policy{}/{}",
+                id, property
+            ))
+            .await
     }
 }
@@ -9,26 +9,32 @@ pub struct Driver {}
 
 #[async_trait]
 impl crate::drivers::Driver for Driver {
-    async fn activate(
-        &self,
-        _power_profile: &super::super::cpu::types::PowerProfile,
-    ) -> Result<()> {
+    async fn activate(&self, _power_profile: &crate::types::PowerProfile) -> Result<()> {
         log::debug!("Activating!");
         Ok(())
     }
 
     async fn current(&self) -> Result<crate::types::InferredPowerProfile> {
         Ok(crate::types::InferredPowerProfile {
-            boost: true,
-            energy_preference: super::super::cpu::types::EnergyPreference::Performance,
-            maximum_frequency: 4000000,
-            scaling_governor: super::super::cpu::types::ScalingGovernor::Performance,
+            boost: Some(true),
+            energy_preference: Some(super::super::cpu::types::EnergyPreference::Performance),
+            maximum_frequency: Some(4000000),
+            scaling_governor: Some(super::super::cpu::types::ScalingGovernor::Performance),
+            ..Default::default()
         })
     }
 
+    fn category(&self) -> crate::drivers::Category {
+        crate::drivers::Category::Cpu
+    }
+
     fn name(&self) -> &str {
         "dummy"
     }
+
+    fn priority(&self) -> crate::drivers::Priority {
+        crate::drivers::Priority::Fallback
+    }
 }
 
 pub async fn probe(
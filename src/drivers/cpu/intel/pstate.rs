@@ -1,15 +1,14 @@
-use anyhow::{Context, Result};
-use async_std::fs;
+use anyhow::Result;
 use async_trait::async_trait;
 use std::str::FromStr;
 
-use crate::drivers::cpu::utils;
+use crate::{drivers::cpu::utils, sysfs::Sysfs};
 
 use super::super::types::{EnergyPreference, ScalingGovernor};
 
 pub(crate) struct Driver {
-    dry_run: bool,
     _status: Status,
+    sysfs: Sysfs,
 }
 
 impl Driver {
@@ -19,66 +18,80 @@ impl Driver {
     const SCALING_GOVERNOR: &'static str =
         "/sys/devices/system/cpu/cpufreq/policy0/scaling_governor";
 
-    pub async fn new(dry_run: bool) -> Result<Self> {
+    pub async fn new(sysfs: Sysfs) -> Result<Self> {
         Ok(Self {
-            dry_run: dry_run,
-            _status: Status::current().await?,
+            _status: Status::current(&sysfs).await?,
+            sysfs,
         })
     }
 
     async fn turbo_enabled(&self) -> Result<bool> {
-        match fs::read_to_string(Self::NO_TURBO_FLAG)
-            .await
-            .with_context(|| format!("Failed to read from {}", Self::NO_TURBO_FLAG))
-        {
-            Ok(res) => Ok(!res.trim().parse()?),
+        match self.sysfs.read(Self::NO_TURBO_FLAG).await {
+            Ok(res) => Ok(!res.parse()?),
             Err(..) => Ok(true),
         }
     }
 
     async fn energy_preference(&self) -> Result<EnergyPreference> {
-        Ok(fs::read_to_string(Self::ENERGY_PREFERENCE)
+        Ok(self
+            .sysfs
+            .read(Self::ENERGY_PREFERENCE)
             .await?
-            .trim()
+            .as_str()
             .try_into()?)
     }
 
     async fn scaling_governor(&self) -> Result<ScalingGovernor> {
-        Ok(fs::read_to_string(Self::SCALING_GOVERNOR)
+        Ok(self
+            .sysfs
+            .read(Self::SCALING_GOVERNOR)
             .await?
-            .trim()
+            .as_str()
             .try_into()?)
     }
 }
 
 #[async_trait]
 impl crate::drivers::Driver for Driver {
-    async fn activate(&self, power_profile: &super::super::types::PowerProfile) -> Result<()> {
-        if self.dry_run {
-            log::debug!("Would have activated power profile {:#?}", power_profile);
+    async fn activate(&self, power_profile: &crate::types::PowerProfile) -> Result<()> {
+        let power_profile = &power_profile.cpu;
 
-            return Ok(());
-        }
-
-        utils::activate_maximum_frequency(power_profile.maximum_frequency).await?;
-        utils::activate_scaling_governor(power_profile.scaling_governor).await?;
-        utils::activate_energy_preference(power_profile.energy_preference).await?;
+        utils::activate_maximum_frequency(&self.sysfs, power_profile.maximum_frequency).await?;
+        utils::activate_scaling_governor(&self.sysfs, power_profile.scaling_governor).await?;
+        utils::activate_energy_preference(&self.sysfs, power_profile.energy_preference).await?;
 
         Ok(())
     }
 
     async fn current(&self) -> Result<crate::types::InferredPowerProfile> {
         Ok(crate::types::InferredPowerProfile {
-            boost: self.turbo_enabled().await?,
-            scaling_governor: self.scaling_governor().await?,
-            energy_preference: self.energy_preference().await?,
-            maximum_frequency: utils::maximum_frequency().await?,
+            boost: Some(self.turbo_enabled().await?),
+            scaling_governor: Some(self.scaling_governor().await?),
+            energy_preference: Some(self.energy_preference().await?),
+            maximum_frequency: Some(utils::maximum_frequency(&self.sysfs).await?),
+            frequency_range: Some(utils::frequency_range(&self.sysfs).await?),
+            ..Default::default()
         })
     }
 
+    async fn check(
+        &self,
+        power_profile: &crate::types::PowerProfile,
+    ) -> Result<Vec<crate::drivers::Finding>> {
+        utils::check_profile(&self.sysfs, &power_profile.cpu).await
+    }
+
+    fn category(&self) -> crate::drivers::Category {
+        crate::drivers::Category::Cpu
+    }
+
     fn name(&self) -> &str {
         "intel_pstate"
     }
+
+    fn priority(&self) -> crate::drivers::Priority {
+        crate::drivers::Priority::Specific
+    }
 }
 
 enum Status {
@@ -90,8 +103,8 @@ enum Status {
 impl Status {
     const PSTATE_STATUS_PATH: &'static str = "/sys/devices/system/cpu/intel_pstate/status";
 
-    async fn current() -> Result<Self> {
-        Self::from_str(&fs::read_to_string(Self::PSTATE_STATUS_PATH).await?.trim())
+    async fn current(sysfs: &Sysfs) -> Result<Self> {
+        Self::from_str(&sysfs.read(Self::PSTATE_STATUS_PATH).await?)
     }
 }
 
@@ -3,21 +3,23 @@ use anyhow::Result;
 use self::types::PowerProfile;
 
 use super::Driver;
+use crate::sysfs::Sysfs;
 
 mod amd;
 pub(crate) mod cpufreq;
 mod dummy;
 mod intel;
 pub(crate) mod types;
-mod utils;
+pub(crate) mod utils;
 
 pub async fn probe(
+    sysfs: &Sysfs,
     profiles: &Vec<PowerProfile>,
 ) -> Vec<Result<std::sync::Arc<dyn Driver + Sync + Send>>> {
     vec![
-        amd::probe(&profiles).await,
-        cpufreq::probe(&profiles).await,
+        amd::probe(sysfs, &profiles).await,
+        cpufreq::probe(sysfs, &profiles).await,
         dummy::probe(&profiles).await,
-        intel::probe(&profiles).await,
+        intel::probe(sysfs, &profiles).await,
     ]
 }
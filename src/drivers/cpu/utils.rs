@@ -1,119 +1,354 @@
-use anyhow::Result;
-use async_std::fs;
+use anyhow::{Context, Result};
 use futures::{StreamExt, TryStreamExt};
 
-// If no max frequency specified, reset it to max by specifying a ludicrous value, like 100 GHz
-const MAX_RESET_FREQUENCY: u32 = 100000000;
+use crate::{drivers::Finding, sysfs::Sysfs, types::StatusPolicy};
+
+const CPUFREQ: &str = "/sys/devices/system/cpu/cpufreq";
 const MAXIMUM_FREQUENCY: &'static str = "/sys/devices/system/cpu/cpufreq/policy0/scaling_max_freq";
 const ONLINE_CPUS: &'static str = "/sys/devices/system/cpu/online";
 
+/// Policy attributes reported in the daemon status
+const STATUS_ATTRIBUTES: &[&str] = &[
+    "affected_cpus",
+    "cpuinfo_max_freq",
+    "cpuinfo_min_freq",
+    "energy_performance_preference",
+    "scaling_cur_freq",
+    "scaling_driver",
+    "scaling_governor",
+    "scaling_max_freq",
+    "scaling_min_freq",
+];
+
+/// A cpufreq policy directory
+#[derive(Clone, Debug)]
+pub(crate) struct CpufreqPolicy {
+    pub id: u32,
+}
+
+impl CpufreqPolicy {
+    pub fn attribute(&self, name: &str) -> String {
+        format!("{}/policy{}/{}", CPUFREQ, self.id, name)
+    }
+}
+
 pub(crate) async fn activate_energy_preference(
+    sysfs: &Sysfs,
     energy_preference: super::types::EnergyPreference,
 ) -> Result<()> {
     log::info!("Activating energy preference {:?}", energy_preference);
 
-    futures::stream::iter(online_cpu_id_iter(&online_cpus().await?)?)
-        .then(|core_id| async move {
-            log::trace!(
-                "Writing {} to /sys/devices/system/cpu/cpufreq/policy{}/energy_performance_preference",
-                energy_preference.to_string(),
-                core_id,
-            );
-
-            fs::write(
-                format!(
-                    "/sys/devices/system/cpu/cpufreq/policy{:?}/energy_performance_preference",
-                    core_id,
-                ),
-                energy_preference.to_string(),
-            )
-            .await
+    futures::stream::iter(online_policies(sysfs).await?)
+        .then(|policy| async move {
+            sysfs
+                .write(
+                    policy.attribute("energy_performance_preference"),
+                    energy_preference.to_string(),
+                )
+                .await
         })
         .try_collect()
         .await
-        .map_err(anyhow::Error::from)
 }
 
-pub(crate) async fn activate_maximum_frequency(maximum_frequency: Option<u32>) -> Result<()> {
-    let value = match maximum_frequency {
-        Some(value) => {
-            log::debug!("Activating maximum frequency {}", value);
-            value
-        }
-        None => {
-            log::debug!("Resetting maximum frequency");
-            MAX_RESET_FREQUENCY
-        }
-    };
+pub(crate) async fn activate_maximum_frequency(
+    sysfs: &Sysfs,
+    maximum_frequency: Option<u32>,
+) -> Result<()> {
+    match maximum_frequency {
+        Some(value) => log::debug!("Activating maximum frequency {}", value),
+        None => log::debug!("Resetting maximum frequency"),
+    }
 
-    futures::stream::iter(online_cpu_id_iter(&online_cpus().await?)?)
-        .then(|core_id| async move {
-            log::trace!(
-                "Writing {} to /sys/devices/system/cpu/cpufreq/policy{}/scaling_max_freq",
-                value,
-                core_id,
-            );
+    futures::stream::iter(online_policies(sysfs).await?)
+        .then(|policy| async move {
+            // Reset to what the hardware supports, anything above it is silently clamped
+            let value = match maximum_frequency {
+                Some(value) => value.to_string(),
+                None => sysfs.read(policy.attribute("cpuinfo_max_freq")).await?,
+            };
 
-            fs::write(
-                format!(
-                    "/sys/devices/system/cpu/cpufreq/policy{}/scaling_max_freq",
-                    core_id,
-                ),
-                value.to_string(),
-            )
-            .await
+            sysfs
+                .write(policy.attribute("scaling_max_freq"), value)
+                .await
         })
         .try_collect()
         .await
-        .map_err(anyhow::Error::from)
 }
 
 pub(crate) async fn activate_scaling_governor(
+    sysfs: &Sysfs,
     scaling_governor: super::types::ScalingGovernor,
 ) -> Result<()> {
     log::info!("Activating scaling governor {:?}", scaling_governor);
 
-    futures::stream::iter(online_cpu_id_iter(&online_cpus().await?)?)
-        .then(|core_id| async move {
-            log::trace!(
-                "Writing {} to /sys/devices/system/cpu/cpufreq/policy{}/scaling_governor",
-                scaling_governor.to_string(),
-                core_id,
-            );
-
-            fs::write(
-                format!(
-                    "/sys/devices/system/cpu/cpufreq/policy{}/scaling_governor",
-                    core_id,
-                ),
-                scaling_governor.to_string(),
-            )
-            .await
+    futures::stream::iter(online_policies(sysfs).await?)
+        .then(|policy| async move {
+            sysfs
+                .write(
+                    policy.attribute("scaling_governor"),
+                    scaling_governor.to_string(),
+                )
+                .await
         })
         .try_collect()
         .await
-        .map_err(anyhow::Error::from)
 }
 
-pub(crate) async fn maximum_frequency() -> Result<u32> {
-    Ok(fs::read_to_string(MAXIMUM_FREQUENCY)
-        .await?
-        .trim()
-        .parse()?)
+pub(crate) async fn maximum_frequency(sysfs: &Sysfs) -> Result<u32> {
+    Ok(sysfs.read(MAXIMUM_FREQUENCY).await?.parse()?)
+}
+
+/// The (minimum, maximum) frequency policy0 supports
+pub(crate) async fn frequency_range(sysfs: &Sysfs) -> Result<(u32, u32)> {
+    let policy = format!("{}/policy0", CPUFREQ);
+
+    Ok((
+        sysfs
+            .read(format!("{}/cpuinfo_min_freq", policy))
+            .await?
+            .parse()?,
+        sysfs
+            .read(format!("{}/cpuinfo_max_freq", policy))
+            .await?
+            .parse()?,
+    ))
+}
+
+pub(crate) async fn online_cpus(sysfs: &Sysfs) -> Result<Vec<u32>> {
+    parse_cpulist(&sysfs.read(ONLINE_CPUS).await?)
+}
+
+/// Every cpufreq policy governing at least one online CPU, ordered by id.
+///
+/// Policy numbers are not CPU ids, a policy is named after the first CPU it was created for.
+pub(crate) async fn online_policies(sysfs: &Sysfs) -> Result<Vec<CpufreqPolicy>> {
+    let online = online_cpus(sysfs).await?;
+    let mut policies = Vec::new();
+
+    for name in sysfs.list(CPUFREQ, "policy").await? {
+        let id = match name["policy".len()..].parse() {
+            Ok(id) => id,
+            Err(..) => continue,
+        };
+
+        let related_cpus = parse_cpulist(
+            &sysfs
+                .read(format!("{}/{}/related_cpus", CPUFREQ, name))
+                .await?,
+        )?;
+
+        if related_cpus.iter().any(|cpu| online.contains(cpu)) {
+            policies.push(CpufreqPolicy { id });
+        }
+    }
+
+    policies.sort_by_key(|policy| policy.id);
+
+    Ok(policies)
+}
+
+/// Check what the pstate drivers write against what every online policy supports
+pub(crate) async fn check_profile(
+    sysfs: &Sysfs,
+    power_profile: &super::types::PowerProfile,
+) -> Result<Vec<Finding>> {
+    let energy_preference = power_profile.energy_preference.to_string();
+    let scaling_governor = power_profile.scaling_governor.to_string();
+
+    let mut energy_preference_failing = Vec::new();
+    let mut scaling_governor_failing = Vec::new();
+    let mut maximum_frequency_failing = Vec::new();
+
+    for policy in online_policies(sysfs).await? {
+        let available = sysfs
+            .read(policy.attribute("energy_performance_available_preferences"))
+            .await
+            .unwrap_or_default();
+
+        if !available
+            .split_whitespace()
+            .any(|item| item == energy_preference)
+        {
+            energy_preference_failing.push(policy.id);
+        }
+
+        let available = sysfs
+            .read(policy.attribute("scaling_available_governors"))
+            .await
+            .unwrap_or_default();
+
+        if !available
+            .split_whitespace()
+            .any(|item| item == scaling_governor)
+        {
+            scaling_governor_failing.push(policy.id);
+        }
+
+        if let Some(maximum_frequency) = power_profile.maximum_frequency {
+            let min: u32 = sysfs
+                .read(policy.attribute("cpuinfo_min_freq"))
+                .await?
+                .parse()?;
+            let max: u32 = sysfs
+                .read(policy.attribute("cpuinfo_max_freq"))
+                .await?
+                .parse()?;
+
+            if !(min..=max).contains(&maximum_frequency) {
+                maximum_frequency_failing.push(policy.id);
+            }
+        }
+    }
+
+    let mut findings = vec![
+        Finding::policies(
+            format!("energy preference {} is available", energy_preference),
+            &energy_preference_failing,
+        ),
+        Finding::policies(
+            format!("scaling governor {} is available", scaling_governor),
+            &scaling_governor_failing,
+        ),
+    ];
+
+    if let Some(maximum_frequency) = power_profile.maximum_frequency {
+        findings.push(Finding::policies(
+            format!(
+                "maximum frequency {} is within cpuinfo_min_freq..cpuinfo_max_freq",
+                maximum_frequency
+            ),
+            &maximum_frequency_failing,
+        ));
+    }
+
+    Ok(findings)
+}
+
+/// The current values of every online policy, attributes a policy lacks are left out
+pub(crate) async fn policy_status(sysfs: &Sysfs) -> Result<Vec<StatusPolicy>> {
+    let mut policies = Vec::new();
+
+    for policy in online_policies(sysfs).await? {
+        let mut values = std::collections::BTreeMap::new();
+
+        for attribute in STATUS_ATTRIBUTES {
+            if let Ok(value) = sysfs.read(policy.attribute(attribute)).await {
+                values.insert(attribute.to_string(), value);
+            }
+        }
+
+        policies.push(StatusPolicy {
+            id: policy.id,
+            values,
+        });
+    }
+
+    Ok(policies)
+}
+
+/// Parse a kernel CPU list such as "0", "0-3,6" or "0-7:2/4" (the first 2 of every 4).
+///
+/// Lists printed by cpufreq, e.g. `related_cpus`, separate CPUs by spaces instead of commas.
+pub(crate) fn parse_cpulist(cpulist: &str) -> Result<Vec<u32>> {
+    let mut cpus = Vec::new();
+
+    for token in cpulist
+        .split(|c: char| c == ',' || c.is_whitespace())
+        .filter(|token| !token.is_empty())
+    {
+        cpus.extend(
+            parse_cpulist_token(token)
+                .with_context(|| format!("Invalid entry {:?} in CPU list {:?}", token, cpulist))?,
+        );
+    }
+
+    cpus.sort_unstable();
+    cpus.dedup();
+
+    Ok(cpus)
 }
 
-pub(crate) async fn online_cpus() -> Result<String, std::io::Error> {
-    fs::read_to_string(ONLINE_CPUS).await
+fn parse_cpulist_token(token: &str) -> Result<impl Iterator<Item = u32>> {
+    let (range, stride) = match token.split_once(':') {
+        Some((range, stride)) => (range, Some(stride)),
+        None => (token, None),
+    };
+
+    let (first, last): (u32, u32) = match range.split_once('-') {
+        Some((first, last)) => (first.parse()?, last.parse()?),
+        None => {
+            let cpu = range.parse()?;
+            (cpu, cpu)
+        }
+    };
+
+    let (used, group): (u32, u32) = match stride {
+        Some(stride) => match stride.split_once('/') {
+            Some((used, group)) => (used.parse()?, group.parse()?),
+            None => return Err(anyhow::anyhow!("stride must be <used>/<group>")),
+        },
+        None => (1, 1),
+    };
+
+    if first > last || used == 0 || used > group {
+        return Err(anyhow::anyhow!("empty range"));
+    }
+
+    Ok((first..=last).filter(move |cpu| (cpu - first) % group < used))
 }
 
-pub(crate) fn online_cpu_id_iter(online_cpus: &String) -> Result<impl Iterator<Item = u32> + '_> {
-    Ok(online_cpus
-        .trim()
-        // "1-5,7-9" -> ["1-5", "7-9"]
-        .split(",")
-        // ["1-5", "7-9", "hello-world", "rust"] -> [["1","5"], ["7","9"], ["hello","world"]]
-        .filter_map(|token| token.split_once("-"))
-        // [["1","5"], ["7","9"], ["hello","world"]] -> [[1,5], [7,9]]]
-        .filter_map(|(first, second)| first.parse::<u32>().ok().zip(second.parse::<u32>().ok()))
-        .flat_map(|(first, second)| (first..=second).into_iter()))
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn cpulist_single_cpu() {
+        assert_eq!(parse_cpulist("0").unwrap(), vec![0]);
+    }
+
+    #[test]
+    fn cpulist_ranges_and_cpus() {
+        assert_eq!(parse_cpulist("0-3,6").unwrap(), vec![0, 1, 2, 3, 6]);
+    }
+
+    #[test]
+    fn cpulist_stride() {
+        assert_eq!(parse_cpulist("0-7:2/4").unwrap(), vec![0, 1, 4, 5]);
+    }
+
+    #[test]
+    fn cpulist_space_separated() {
+        // As printed by related_cpus, including the trailing newline
+        assert_eq!(parse_cpulist("0 1 2 3\n").unwrap(), vec![0, 1, 2, 3]);
+    }
+
+    #[test]
+    fn cpulist_sorted_and_deduplicated() {
+        assert_eq!(parse_cpulist("4,0-2,1").unwrap(), vec![0, 1, 2, 4]);
+    }
+
+    #[test]
+    fn cpulist_empty() {
+        assert_eq!(parse_cpulist("").unwrap(), Vec::<u32>::new());
+    }
+
+    #[test]
+    fn cpulist_invalid() {
+        for cpulist in ["a", "3-1", "0-", "-1", "0-7:2", "0-7:0/4", "0-7:5/4"] {
+            assert!(
+                parse_cpulist(cpulist).is_err(),
+                "{:?} was accepted",
+                cpulist
+            );
+        }
+    }
+
+    #[test]
+    fn cpulist_token_stride() {
+        assert_eq!(
+            parse_cpulist_token("2-9:1/3").unwrap().collect::<Vec<_>>(),
+            vec![2, 5, 8]
+        );
+    }
 }
@@ -1,4 +1,4 @@
-use std::sync::Arc;
+use std::{collections::BTreeMap, fmt, sync::Arc};
 
 use anyhow::Result;
 use async_trait::async_trait;
@@ -6,47 +6,224 @@ use async_trait::async_trait;
 use self::cpu::types::PowerProfile;
 
 pub(crate) mod cpu;
+pub(crate) mod platform;
+
+// No GPU or peripheral drivers exist yet
+#[allow(dead_code)]
+#[derive(Clone, Copy, Debug, Eq, Ord, PartialEq, PartialOrd)]
+pub(crate) enum Category {
+    Cpu,
+    Platform,
+    Gpu,
+    Peripheral,
+}
+
+/// Within a category, drivers with a higher priority are preferred
+#[derive(Clone, Copy, Debug, Eq, Ord, PartialEq, PartialOrd)]
+pub(crate) enum Priority {
+    /// Only used when nothing else could be loaded
+    Fallback,
+    /// Works on any hardware exposing a generic kernel interface
+    Generic,
+    /// Targets a specific kernel driver
+    Specific,
+}
 
 #[async_trait]
 pub(crate) trait Driver: Send + Sync {
-    async fn activate(&self, power_profile: &cpu::types::PowerProfile) -> Result<()>;
+    async fn activate(&self, power_profile: &crate::types::PowerProfile) -> Result<()>;
+    /// Only the fields this driver is responsible for are filled in
     async fn current(&self) -> Result<crate::types::InferredPowerProfile>;
+    fn category(&self) -> Category;
     fn name(&self) -> &str;
+
+    fn priority(&self) -> Priority {
+        Priority::Generic
+    }
+
+    /// An exclusive driver owns its category, lower priority exclusive drivers are not loaded
+    fn exclusive(&self) -> bool {
+        true
+    }
+
+    /// Check whether the hardware supports everything a profile asks of this driver
+    async fn check(&self, _power_profile: &crate::types::PowerProfile) -> Result<Vec<Finding>> {
+        Ok(Vec::new())
+    }
+}
+
+/// The outcome of checking one aspect of a profile against the hardware
+#[derive(Debug)]
+pub(crate) struct Finding {
+    pub ok: bool,
+    /// The hardware lacks the attribute and activation leaves it alone, which is fine
+    pub skipped: bool,
+    pub message: String,
+}
+
+impl Finding {
+    pub fn new(ok: bool, message: impl Into<String>) -> Self {
+        Self {
+            ok,
+            skipped: false,
+            message: message.into(),
+        }
+    }
+
+    pub fn skipped(message: impl Into<String>) -> Self {
+        Self {
+            ok: true,
+            skipped: true,
+            message: message.into(),
+        }
+    }
+
+    /// A single finding for a check made on every cpufreq policy
+    pub fn policies(what: impl fmt::Display, failing: &[u32]) -> Self {
+        match failing.is_empty() {
+            true => Self::new(true, format!("{} on every policy", what)),
+            false => Self::new(
+                false,
+                format!(
+                    "{} except on {}",
+                    what,
+                    failing
+                        .iter()
+                        .map(|id| format!("policy{}", id))
+                        .collect::<Vec<_>>()
+                        .join(", ")
+                ),
+            ),
+        }
+    }
 }
 
-#[derive(Clone)]
+/// Outcome of activating a profile on every driver
+#[derive(Debug)]
+pub(crate) struct ActivationReport {
+    results: Vec<(String, Result<()>)>,
+}
+
+impl ActivationReport {
+    pub fn succeeded(&self) -> bool {
+        self.results.iter().all(|(_, result)| result.is_ok())
+    }
+}
+
+impl fmt::Display for ActivationReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let results: Vec<String> = self
+            .results
+            .iter()
+            .map(|(name, result)| match result {
+                Ok(()) => format!("{}: ok", name),
+                Err(err) => format!("{}: {:#}", name, err),
+            })
+            .collect();
+
+        write!(f, "Profile activation failed ({})", results.join(", "))
+    }
+}
+
+impl std::error::Error for ActivationReport {}
+
+#[derive(Clone, Default)]
 pub(crate) struct DriverSet {
-    pub cpu: Arc<dyn crate::drivers::Driver + std::marker::Send + Sync>,
+    drivers: BTreeMap<Category, Vec<Arc<dyn Driver + Send + Sync>>>,
 }
 
 impl DriverSet {
+    pub fn new(mut candidates: Vec<Arc<dyn Driver + Send + Sync>>) -> Self {
+        candidates.sort_by_key(|driver| std::cmp::Reverse(driver.priority()));
+
+        let mut drivers: BTreeMap<Category, Vec<Arc<dyn Driver + Send + Sync>>> = BTreeMap::new();
+
+        for driver in candidates {
+            let loaded = drivers.entry(driver.category()).or_default();
+
+            if driver.exclusive() && loaded.iter().any(|other| other.exclusive()) {
+                log::debug!(
+                    "Skipping driver {}, {:?} is already handled",
+                    driver.name(),
+                    driver.category()
+                );
+                continue;
+            }
+
+            log::info!("Using {:?} driver {}", driver.category(), driver.name());
+            loaded.push(driver);
+        }
+
+        Self { drivers }
+    }
+
+    pub fn drivers(&self, category: Category) -> &[Arc<dyn Driver + Send + Sync>] {
+        self.drivers
+            .get(&category)
+            .map(|drivers| drivers.as_slice())
+            .unwrap_or_default()
+    }
+
+    /// Names of the drivers of a category, as reported over D-Bus
+    pub fn names(&self, category: Category) -> String {
+        self.drivers(category)
+            .iter()
+            .map(|driver| driver.name())
+            .collect::<Vec<_>>()
+            .join(",")
+    }
+
+    fn all(&self) -> impl Iterator<Item = &Arc<dyn Driver + Send + Sync>> {
+        self.drivers.values().flatten()
+    }
+
+    /// Activate a profile on every driver concurrently
     pub async fn activate(&self, power_profile: &crate::types::PowerProfile) -> Result<()> {
-        self.cpu.activate(&power_profile.cpu).await
-
-        // futures::future::join_all(
-        //     self.cpu
-        //         .clone()
-        //         .into_iter()
-        //         .map(|driver| driver.activate(power_profile)),
-        // )
-        // .await
-        // .into_iter()
-        // .collect::<Vec<Result<_, _>>>();
+        let results = futures::future::join_all(self.all().map(|driver| async move {
+            (
+                driver.name().to_string(),
+                driver.activate(power_profile).await,
+            )
+        }))
+        .await;
+
+        let report = ActivationReport { results };
+
+        match report.succeeded() {
+            true => Ok(()),
+            false => Err(report.into()),
+        }
+    }
+
+    /// Check a profile against every driver, with the name of each driver
+    pub async fn check(
+        &self,
+        power_profile: &crate::types::PowerProfile,
+    ) -> Vec<(String, Result<Vec<Finding>>)> {
+        let mut results = Vec::new();
+
+        for driver in self.all() {
+            results.push((driver.name().to_string(), driver.check(power_profile).await));
+        }
+
+        results
+    }
+
+    pub async fn current(&self) -> Result<crate::types::InferredPowerProfile> {
+        let mut inferred = crate::types::InferredPowerProfile::default();
+
+        for driver in self.all() {
+            inferred = inferred.merge(driver.current().await?);
+        }
+
+        Ok(inferred)
     }
 }
 
-pub(crate) async fn probe(settings: &crate::settings::Settings) -> Result<DriverSet> {
-    let cpu_drivers = cpu::probe(
-        &settings
-            .profiles()
-            .clone()
-            .into_values()
-            .map(|profile| PowerProfile::from(profile))
-            .collect(),
-    )
-    .await
-    .into_iter()
-    .filter_map(|driver| match driver {
+fn loaded(
+    drivers: Vec<Result<Arc<dyn Driver + Send + Sync>>>,
+) -> impl Iterator<Item = Arc<dyn Driver + Send + Sync>> {
+    drivers.into_iter().filter_map(|driver| match driver {
         Ok(res) => {
             log::trace!("Loaded driver {:#?}", res.name());
             Some(res)
@@ -56,12 +233,44 @@ pub(crate) async fn probe(settings: &crate::settings::Settings) -> Result<Driver
             None
         }
     })
-    .collect::<Vec<_>>();
+}
 
-    // FIXME
-    let cpu_driver = cpu_drivers.into_iter().next();
+pub(crate) async fn probe(
+    settings: &crate::settings::Settings,
+    sysfs: &crate::sysfs::Sysfs,
+) -> Result<DriverSet> {
+    log::debug!("Probing drivers below {:?}", sysfs.path("/"));
 
-    Ok(DriverSet {
-        cpu: cpu_driver.unwrap(),
-    })
+    let cpu_drivers = cpu::probe(
+        sysfs,
+        &settings
+            .profiles()
+            .clone()
+            .into_values()
+            .map(|profile| PowerProfile::from(profile))
+            .collect(),
+    )
+    .await;
+
+    let platform_drivers = platform::probe(
+        sysfs,
+        &settings
+            .profiles()
+            .values()
+            .filter_map(|profile| profile.platform.clone())
+            .collect(),
+    )
+    .await;
+
+    let driver_set = DriverSet::new(
+        loaded(cpu_drivers)
+            .chain(loaded(platform_drivers))
+            .collect(),
+    );
+
+    if driver_set.drivers(Category::Cpu).is_empty() {
+        return Err(anyhow::anyhow!("No usable CPU driver found"));
+    }
+
+    Ok(driver_set)
 }
@@ -0,0 +1,110 @@
+use std::sync::Arc;
+
+use anyhow::Result;
+use async_trait::async_trait;
+
+use super::types::PowerProfile;
+use crate::sysfs::Sysfs;
+
+const PLATFORM_PROFILE: &str = "/sys/firmware/acpi/platform_profile";
+const PLATFORM_PROFILE_CHOICES: &str = "/sys/firmware/acpi/platform_profile_choices";
+
+#[derive(Debug)]
+pub(crate) struct Driver {
+    choices: Vec<String>,
+    sysfs: Sysfs,
+}
+
+impl Driver {
+    async fn platform_profile(&self) -> Result<String> {
+        self.sysfs.read(PLATFORM_PROFILE).await
+    }
+}
+
+#[async_trait]
+impl crate::drivers::Driver for Driver {
+    async fn activate(&self, power_profile: &crate::types::PowerProfile) -> Result<()> {
+        let Some(platform) = &power_profile.platform else {
+            log::debug!(
+                "No platform profile for {}, leaving it alone",
+                power_profile.name
+            );
+            return Ok(());
+        };
+
+        if !self.choices.contains(&platform.profile) {
+            return Err(anyhow::anyhow!(
+                "Platform profile {} is not one of {}",
+                platform.profile,
+                self.choices.join(", ")
+            ));
+        }
+
+        log::info!("Activating platform profile {}", platform.profile);
+
+        self.sysfs.write(PLATFORM_PROFILE, &platform.profile).await
+    }
+
+    async fn current(&self) -> Result<crate::types::InferredPowerProfile> {
+        Ok(crate::types::InferredPowerProfile {
+            platform_profile: Some(self.platform_profile().await?),
+            ..Default::default()
+        })
+    }
+
+    async fn check(
+        &self,
+        power_profile: &crate::types::PowerProfile,
+    ) -> Result<Vec<crate::drivers::Finding>> {
+        let Some(platform) = &power_profile.platform else {
+            return Ok(Vec::new());
+        };
+
+        Ok(vec![crate::drivers::Finding::new(
+            self.choices.contains(&platform.profile),
+            format!(
+                "platform profile {} is one of {}",
+                platform.profile,
+                self.choices.join(", ")
+            ),
+        )])
+    }
+
+    fn category(&self) -> crate::drivers::Category {
+        crate::drivers::Category::Platform
+    }
+
+    fn name(&self) -> &str {
+        "platform_profile"
+    }
+}
+
+pub async fn probe(
+    sysfs: &Sysfs,
+    profiles: &Vec<PowerProfile>,
+) -> Result<Arc<dyn crate::drivers::Driver + Send + Sync>> {
+    let choices: Vec<String> = sysfs
+        .read(PLATFORM_PROFILE_CHOICES)
+        .await?
+        .split_whitespace()
+        .map(|choice| choice.to_owned())
+        .collect();
+
+    for profile in profiles {
+        if !choices.contains(&profile.profile) {
+            log::warn!(
+                "Platform profile {} is not supported, choose from {}",
+                profile.profile,
+                choices.join(", ")
+            );
+        }
+    }
+
+    let driver = Driver {
+        choices,
+        sysfs: sysfs.clone(),
+    };
+    log::trace!("Loaded {:#?}", driver);
+
+    Ok(Arc::new(driver))
+}
@@ -0,0 +1,16 @@
+use anyhow::Result;
+
+use self::types::PowerProfile;
+
+use super::Driver;
+use crate::sysfs::Sysfs;
+
+mod acpi;
+pub(crate) mod types;
+
+pub async fn probe(
+    sysfs: &Sysfs,
+    profiles: &Vec<PowerProfile>,
+) -> Vec<Result<std::sync::Arc<dyn Driver + Sync + Send>>> {
+    vec![acpi::probe(sysfs, profiles).await]
+}
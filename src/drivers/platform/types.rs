@@ -0,0 +1,7 @@
+use serde::Deserialize;
+
+#[derive(Clone, Debug, Deserialize)]
+pub struct PowerProfile {
+    /// One of the choices offered by the platform, e.g. `low-power` or `performance`
+    pub(crate) profile: String,
+}
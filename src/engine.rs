@@ -0,0 +1,1256 @@
+use std::{
+    collections::{BTreeMap, BTreeSet, HashSet},
+    sync::{Arc, RwLock},
+};
+
+use async_std::{
+    channel::{Receiver, Sender},
+    sync::Mutex,
+};
+
+use crate::{
+    actions, drivers,
+    holds::ProfileHolds,
+    persist,
+    settings::Settings,
+    sysfs::{self, Sysfs},
+    types::{PowerProfileHold, Status, StatusDriver, StatusHold},
+};
+
+/// State changes the D-Bus interfaces need to relay to their clients
+#[derive(Clone, Debug)]
+pub(crate) enum Event {
+    ActiveProfile,
+    ProfileHolds,
+    ProfileReleased {
+        cookie: u32,
+        requester: String,
+    },
+    /// A profile activation recorded a new plan of writes
+    Plan,
+    /// A profile activation was verified
+    Mismatches,
+    /// The configured profiles were reloaded
+    Profiles,
+    /// The actions in use changed, on reload or as the low battery profile comes and goes
+    Actions,
+    /// A reason performance is degraded appeared or went away
+    PerformanceDegraded,
+}
+
+/// Reasons the daemon switches profiles by itself, later ones win over earlier ones
+#[derive(Clone, Copy, Debug, Eq, Ord, PartialEq, PartialOrd)]
+pub(crate) enum Trigger {
+    PowerSource,
+    LowBattery,
+    Thermal,
+}
+
+impl Trigger {
+    /// Forced profiles win over holds and stay when the user selects a profile
+    fn forces(&self) -> bool {
+        matches!(self, Self::Thermal)
+    }
+}
+
+/// Shown in the Actions property while the low battery profile is in use
+pub(crate) const LOW_BATTERY_ACTION: &str = "low_battery";
+
+#[derive(Default)]
+struct State {
+    profile_holds: ProfileHolds,
+    /// The profile last chosen by the user, restored once every hold is released
+    selected_profile: Option<String>,
+    /// Chosen by the daemon, e.g. on battery power, until the user selects a profile
+    automatic_profiles: BTreeMap<Trigger, String>,
+    /// Peers whose disconnects are being watched
+    requesters: HashSet<String>,
+    /// Reasons performance is currently degraded, e.g. "lap-detected"
+    degradation_reasons: BTreeSet<String>,
+    /// Profiles that can't be activated right now, with the reason
+    blocked_profiles: BTreeMap<String, String>,
+}
+
+/// Daemon state shared by every D-Bus interface, so they always agree with each other.
+#[derive(Clone)]
+pub(crate) struct Engine {
+    driver_set: Arc<RwLock<drivers::DriverSet>>,
+    action_set: Arc<RwLock<actions::ActionSet>>,
+    settings: Arc<RwLock<Arc<Settings>>>,
+    /// Where the settings are reloaded from
+    config_path: String,
+    sysfs: Sysfs,
+    state: Arc<Mutex<State>>,
+    /// Writes made, or in dry-run mode planned, by the last profile activation
+    plan: Arc<std::sync::Mutex<Vec<sysfs::Write>>>,
+    /// Attributes that did not take the value written by the last profile activation
+    mismatches: Arc<std::sync::Mutex<Vec<sysfs::Mismatch>>>,
+    subscribers: Arc<std::sync::Mutex<Vec<Sender<Event>>>>,
+}
+
+impl Engine {
+    pub fn new(
+        driver_set: drivers::DriverSet,
+        action_set: actions::ActionSet,
+        settings: Settings,
+        config_path: String,
+        sysfs: Sysfs,
+    ) -> Self {
+        Self {
+            driver_set: Arc::new(RwLock::new(driver_set)),
+            action_set: Arc::new(RwLock::new(action_set)),
+            settings: Arc::new(RwLock::new(Arc::new(settings))),
+            config_path,
+            sysfs,
+            state: Arc::new(Mutex::new(State::default())),
+            plan: Arc::new(std::sync::Mutex::new(Vec::new())),
+            mismatches: Arc::new(std::sync::Mutex::new(Vec::new())),
+            subscribers: Arc::new(std::sync::Mutex::new(Vec::new())),
+        }
+    }
+
+    pub fn driver_set(&self) -> drivers::DriverSet {
+        self.driver_set.read().unwrap().clone()
+    }
+
+    pub fn action_set(&self) -> actions::ActionSet {
+        self.action_set.read().unwrap().clone()
+    }
+
+    /// The settings in use right now, a reload doesn't change the returned instance
+    pub fn settings(&self) -> Arc<Settings> {
+        self.settings.read().unwrap().clone()
+    }
+
+    pub fn sysfs(&self) -> &Sysfs {
+        &self.sysfs
+    }
+
+    pub fn dry_run(&self) -> bool {
+        self.sysfs.dry_run()
+    }
+
+    pub fn plan(&self) -> Vec<sysfs::Write> {
+        self.plan.lock().unwrap().clone()
+    }
+
+    pub fn mismatches(&self) -> Vec<sysfs::Mismatch> {
+        self.mismatches.lock().unwrap().clone()
+    }
+
+    /// Apply the profile the user selected before the last shutdown, or the default one
+    pub async fn restore(&self) -> anyhow::Result<()> {
+        let mut state = self.state.lock().await;
+        let settings = self.settings();
+
+        let profile = match persist::load(&settings.state_file).await {
+            Some(profile) if settings.profile_by_name(&profile).is_some() => profile,
+            Some(profile) => {
+                log::warn!(
+                    "Stored profile {} is no longer configured, using {}",
+                    profile,
+                    settings.default
+                );
+                settings.default.clone()
+            }
+            None => settings.default.clone(),
+        };
+
+        log::info!("Restoring profile {}", profile);
+
+        self.activate_profile(&profile).await?;
+        state.selected_profile = Some(profile);
+        self.emit(Event::ActiveProfile);
+
+        Ok(())
+    }
+
+    pub fn subscribe(&self) -> Receiver<Event> {
+        let (sender, receiver) = async_std::channel::unbounded();
+
+        self.subscribers.lock().unwrap().push(sender);
+
+        receiver
+    }
+
+    pub fn emit(&self, event: Event) {
+        self.subscribers
+            .lock()
+            .unwrap()
+            .retain(|subscriber| subscriber.try_send(event.clone()).is_ok());
+    }
+
+    pub async fn active_profile(&self) -> anyhow::Result<String, zbus::fdo::Error> {
+        let state = self.state.lock().await;
+
+        self.current_profile(&state).await
+    }
+
+    async fn current_profile(&self, state: &State) -> anyhow::Result<String, zbus::fdo::Error> {
+        let effective = self.effective_profile(state);
+
+        // Nothing was written, so the hardware can't tell what is active
+        if self.dry_run() {
+            return Ok(effective);
+        }
+
+        match self.infer_profile(&effective).await {
+            Ok(Some(profile)) => Ok(profile),
+            Ok(None) => {
+                log::warn!("Unable to determine current profile");
+                Ok(self.settings().default.clone())
+            }
+            Err(err) => Err(zbus::fdo::Error::Failed(format!("{:?}", err)))?,
+        }
+    }
+
+    /// The configured profile matching what the hardware is currently doing, if any
+    pub async fn inferred_profile(&self) -> anyhow::Result<Option<String>> {
+        let effective = self.effective_profile(&*self.state.lock().await);
+
+        self.infer_profile(&effective).await
+    }
+
+    /// Profiles can be indistinguishable on some hardware, `preferred` wins if it matches
+    async fn infer_profile(&self, preferred: &str) -> anyhow::Result<Option<String>> {
+        Ok(self
+            .settings()
+            .profile_by_inferred(self.driver_set().current().await?, preferred)
+            .map(|profile| profile.name))
+    }
+
+    /// Re-apply the effective profile if the hardware no longer matches it.
+    ///
+    /// Returns true if the profile had drifted.
+    pub async fn reapply_if_drifted(&self) -> anyhow::Result<bool> {
+        let state = self.state.lock().await;
+
+        // Nothing was ever applied by us, so there is nothing to drift from
+        if self.dry_run()
+            || (state.selected_profile.is_none()
+                && state.automatic_profiles.is_empty()
+                && state.profile_holds.is_empty())
+        {
+            return Ok(false);
+        }
+
+        let expected = self.effective_profile(&state);
+
+        if self.infer_profile(&expected).await?.as_ref() == Some(&expected) {
+            return Ok(false);
+        }
+
+        log::warn!("Profile drifted from {}, re-applying", expected);
+
+        self.activate_profile(&expected).await?;
+        self.emit(Event::ActiveProfile);
+
+        Ok(true)
+    }
+
+    /// Apply the effective profile again, e.g. to CPUs that just came online
+    pub async fn reapply(&self) -> anyhow::Result<()> {
+        let state = self.state.lock().await;
+
+        log::info!("Re-applying profile {}", self.effective_profile(&state));
+
+        self.activate_effective_profile(&state).await?;
+        self.emit(Event::ActiveProfile);
+
+        Ok(())
+    }
+
+    /// Load the config file again and swap it in if this machine can use it.
+    ///
+    /// The running settings are untouched if the new ones fail to load, or if a profile
+    /// something holds is gone.
+    pub async fn reload(&self) -> anyhow::Result<()> {
+        let settings = Settings::build(&self.config_path)?;
+
+        // Probing deserializes the driver options of every profile again
+        let driver_set = drivers::probe(&settings, &self.sysfs).await?;
+        let action_set = actions::probe(&settings, &self.sysfs).await;
+
+        let mut state = self.state.lock().await;
+
+        for hold in state.profile_holds.values() {
+            if settings.profile_by_name(&hold.profile).is_none() {
+                return Err(anyhow::anyhow!(
+                    "Profile {} is held by {} but no longer configured",
+                    hold.profile,
+                    hold.application_id
+                ));
+            }
+        }
+
+        let previous = self.settings();
+
+        // Taken before the state is fixed up below, which may change the effective profile
+        let before = self.effective_profile(&state);
+        let before = (
+            previous
+                .profile_by_name(&before)
+                .map(|profile| profile.to_string()),
+            before,
+        );
+
+        if settings.sysfs_root != previous.sysfs_root {
+            log::warn!(
+                "Changing sysfs_root requires a restart, keeping {}",
+                previous.sysfs_root
+            );
+        }
+
+        if let Some(selected) = &state.selected_profile {
+            if settings.profile_by_name(selected).is_none() {
+                log::warn!(
+                    "Selected profile {} is no longer configured, using {}",
+                    selected,
+                    settings.default
+                );
+                state.selected_profile = None;
+            }
+        }
+
+        state.automatic_profiles.retain(|trigger, automatic| {
+            let configured = settings.profile_by_name(automatic).is_some();
+
+            if !configured {
+                log::warn!(
+                    "Profile {} chosen on {:?} is no longer configured",
+                    automatic,
+                    trigger
+                );
+            }
+
+            configured
+        });
+
+        *self.driver_set.write().unwrap() = driver_set;
+        *self.action_set.write().unwrap() = action_set;
+        *self.settings.write().unwrap() = Arc::new(settings);
+
+        log::info!("Reloaded settings from {}", self.config_path);
+        self.emit(Event::Profiles);
+        self.emit(Event::Actions);
+
+        let active = self.effective_profile(&state);
+        let after = (
+            self.settings()
+                .profile_by_name(&active)
+                .map(|profile| profile.to_string()),
+            active.clone(),
+        );
+
+        if before != after {
+            log::info!("Re-applying {} after the reload changed it", active);
+
+            self.activate_profile(&active).await?;
+            self.emit(Event::ActiveProfile);
+        }
+
+        Ok(())
+    }
+
+    pub async fn set_active_profile(&self, name: String) -> anyhow::Result<(), zbus::fdo::Error> {
+        let mut state = self.state.lock().await;
+
+        if self.settings().profile_by_name(&name).is_none() {
+            log::warn!("Received request to select missing profile {}", name);
+
+            return Err(zbus::fdo::Error::InvalidArgs("No such profile".to_string()));
+        }
+
+        Self::check_blocked(&state, &name)?;
+
+        // A forced profile stays active, the selection applies once it is lifted
+        self.activate_profile(&self.forced_profile(&state).unwrap_or_else(|| name.clone()))
+            .await?;
+
+        if self.dry_run() {
+            log::info!("Would have persisted selected profile {}", name);
+        } else if let Err(err) = persist::store(&self.settings().state_file, &name).await {
+            log::warn!("Unable to persist selected profile: {:?}", err);
+        }
+
+        let low_battery = Self::low_battery(&state);
+
+        state.selected_profile = Some(name);
+        state
+            .automatic_profiles
+            .retain(|trigger, _| trigger.forces());
+        self.emit(Event::ActiveProfile);
+
+        if low_battery {
+            self.emit(Event::Actions);
+        }
+
+        // A manual selection cancels every outstanding hold
+        let released = state.profile_holds.drain();
+
+        if !released.is_empty() {
+            self.emit(Event::ProfileHolds);
+        }
+
+        for (cookie, hold, requester) in released {
+            log::info!(
+                "Releasing hold {} from {} after manual profile change",
+                cookie,
+                hold.application_id
+            );
+
+            self.emit(Event::ProfileReleased { cookie, requester });
+        }
+
+        Ok(())
+    }
+
+    /// Switch to a profile on the user's behalf, `None` withdraws what `trigger` chose.
+    ///
+    /// Holds win, and selecting a profile overrides it, unless the trigger forces its profile.
+    pub async fn set_automatic_profile(
+        &self,
+        trigger: Trigger,
+        profile: Option<String>,
+    ) -> anyhow::Result<(), zbus::fdo::Error> {
+        let mut state = self.state.lock().await;
+
+        if state.automatic_profiles.get(&trigger) == profile.as_ref() {
+            return Ok(());
+        }
+
+        let previous = match profile {
+            Some(profile) => {
+                if self.settings().profile_by_name(&profile).is_none() {
+                    return Err(zbus::fdo::Error::InvalidArgs("No such profile".to_string()));
+                }
+
+                state.automatic_profiles.insert(trigger, profile)
+            }
+            None => state.automatic_profiles.remove(&trigger),
+        };
+
+        if let Err(err) = self.activate_effective_profile(&state).await {
+            match previous {
+                Some(previous) => state.automatic_profiles.insert(trigger, previous),
+                None => state.automatic_profiles.remove(&trigger),
+            };
+
+            return Err(err);
+        }
+
+        self.emit(Event::ActiveProfile);
+
+        if trigger == Trigger::LowBattery {
+            self.emit(Event::Actions);
+        }
+
+        Ok(())
+    }
+
+    /// Names of the automations in use, for the Actions property
+    pub async fn actions(&self) -> Vec<String> {
+        let mut actions = self.action_set().names();
+
+        if Self::low_battery(&*self.state.lock().await) {
+            actions.push(LOW_BATTERY_ACTION.to_owned());
+        }
+
+        actions
+    }
+
+    fn low_battery(state: &State) -> bool {
+        state.automatic_profiles.contains_key(&Trigger::LowBattery)
+    }
+
+    pub async fn profile_holds(&self) -> Vec<PowerProfileHold> {
+        self.state.lock().await.profile_holds.values()
+    }
+
+    pub async fn status(&self) -> anyhow::Result<Status> {
+        let active_profile = self.active_profile().await?;
+
+        // Not every system has cpufreq policies
+        let policies = match drivers::cpu::utils::policy_status(&self.sysfs).await {
+            Ok(policies) => policies,
+            Err(err) => {
+                log::debug!("Unable to read cpufreq policies: {}", err);
+                Vec::new()
+            }
+        };
+
+        let driver_set = self.driver_set();
+        let drivers = [drivers::Category::Cpu, drivers::Category::Platform]
+            .into_iter()
+            .flat_map(|category| {
+                driver_set
+                    .drivers(category)
+                    .iter()
+                    .map(move |driver| StatusDriver {
+                        category: format!("{:?}", category).to_lowercase(),
+                        name: driver.name().to_owned(),
+                    })
+            })
+            .collect();
+
+        let state = self.state.lock().await;
+
+        Ok(Status {
+            active_profile,
+            holds: state
+                .profile_holds
+                .iter()
+                .map(|(cookie, hold)| StatusHold {
+                    cookie,
+                    profile: hold.profile.clone(),
+                    application_id: hold.application_id.clone(),
+                    reason: hold.reason.clone(),
+                })
+                .collect(),
+            drivers,
+            performance_degraded: state.degradation_reasons.iter().cloned().collect(),
+            policies,
+        })
+    }
+
+    /// Add or remove a reason performance is degraded, e.g. "high-operating-temperature"
+    pub async fn set_degraded(&self, reason: &str, degraded: bool) {
+        let mut state = self.state.lock().await;
+
+        let changed = match degraded {
+            true => state.degradation_reasons.insert(reason.to_owned()),
+            false => state.degradation_reasons.remove(reason),
+        };
+
+        if changed {
+            log::info!(
+                "Performance {} ({})",
+                if degraded { "degraded" } else { "restored" },
+                reason
+            );
+
+            self.emit(Event::PerformanceDegraded);
+        }
+    }
+
+    /// Refuse or allow a profile, e.g. performance while on a lap.
+    ///
+    /// A blocked profile that is selected or held is replaced by the default one until it
+    /// is allowed again.
+    pub async fn set_blocked(
+        &self,
+        profile: &str,
+        reason: &str,
+        blocked: bool,
+    ) -> anyhow::Result<(), zbus::fdo::Error> {
+        let mut state = self.state.lock().await;
+        let before = self.effective_profile(&state);
+
+        let changed = match blocked {
+            true => state
+                .blocked_profiles
+                .insert(profile.to_owned(), reason.to_owned())
+                .is_none(),
+            false => state.blocked_profiles.remove(profile).is_some(),
+        };
+
+        if !changed {
+            return Ok(());
+        }
+
+        log::info!(
+            "Profile {} {} ({})",
+            profile,
+            if blocked { "blocked" } else { "allowed" },
+            reason
+        );
+
+        if self.effective_profile(&state) != before {
+            self.activate_effective_profile(&state).await?;
+            self.emit(Event::ActiveProfile);
+        }
+
+        Ok(())
+    }
+
+    fn check_blocked(state: &State, profile: &str) -> anyhow::Result<(), zbus::fdo::Error> {
+        match state.blocked_profiles.get(profile) {
+            Some(reason) => Err(zbus::fdo::Error::Failed(format!(
+                "Profile {} is blocked: {}",
+                profile, reason
+            ))),
+            None => Ok(()),
+        }
+    }
+
+    pub async fn performance_degraded(&self) -> String {
+        let state = self.state.lock().await;
+
+        state
+            .degradation_reasons
+            .iter()
+            .cloned()
+            .collect::<Vec<_>>()
+            .join(",")
+    }
+
+    pub async fn hold_profile(
+        &self,
+        profile: &str,
+        reason: &str,
+        application_id: &str,
+        requester: &str,
+    ) -> anyhow::Result<u32, zbus::fdo::Error> {
+        if !ProfileHolds::holdable(profile) {
+            return Err(zbus::fdo::Error::InvalidArgs(
+                "Only profiles 'performance' and 'power-saver' can be a hold".to_string(),
+            ));
+        }
+
+        if self
+            .settings()
+            .profile_by_name(&profile.to_string())
+            .is_none()
+        {
+            return Err(zbus::fdo::Error::InvalidArgs("No such profile".to_string()));
+        }
+
+        let mut state = self.state.lock().await;
+
+        Self::check_blocked(&state, profile)?;
+
+        // Remember what to go back to once the last hold is released
+        if state.profile_holds.is_empty()
+            && state.selected_profile.is_none()
+            && state.automatic_profiles.is_empty()
+        {
+            state.selected_profile = Some(self.current_profile(&state).await?);
+        }
+
+        let cookie = state.profile_holds.insert(
+            PowerProfileHold::new(
+                application_id.to_owned(),
+                profile.to_owned(),
+                reason.to_owned(),
+            ),
+            requester.to_owned(),
+        );
+
+        if let Err(err) = self.activate_effective_profile(&state).await {
+            state.profile_holds.remove(cookie);
+
+            return Err(err);
+        }
+
+        self.emit(Event::ProfileHolds);
+        self.emit(Event::ActiveProfile);
+
+        log::info!(
+            "Added profile hold {} for {} from {} ({})",
+            cookie,
+            profile,
+            application_id,
+            requester
+        );
+
+        Ok(cookie)
+    }
+
+    pub async fn release_profile(&self, cookie: u32) -> anyhow::Result<(), zbus::fdo::Error> {
+        let mut state = self.state.lock().await;
+
+        let requester = match state.profile_holds.remove(cookie) {
+            Some((hold, requester)) => {
+                log::info!(
+                    "Removed profile hold {} from {}",
+                    cookie,
+                    hold.application_id
+                );
+
+                requester
+            }
+            None => {
+                log::info!(
+                    "Request to remove profile hold for missing cookie {}",
+                    cookie
+                );
+
+                return Err(zbus::fdo::Error::InvalidArgs(format!(
+                    "No hold with cookie {}",
+                    cookie
+                )));
+            }
+        };
+
+        self.emit(Event::ProfileReleased { cookie, requester });
+        self.emit(Event::ProfileHolds);
+
+        let result = self.activate_effective_profile(&state).await;
+        self.emit(Event::ActiveProfile);
+
+        result
+    }
+
+    /// Returns true if `requester` was not watched for disconnects yet
+    pub async fn track_requester(&self, requester: &str) -> bool {
+        !requester.is_empty()
+            && self
+                .state
+                .lock()
+                .await
+                .requesters
+                .insert(requester.to_owned())
+    }
+
+    /// Forget a peer whose disconnects could not be watched, so its next hold tries again
+    pub async fn untrack_requester(&self, requester: &str) {
+        self.state.lock().await.requesters.remove(requester);
+    }
+
+    /// Drop the holds of a peer that left the bus and restore the profile it was holding
+    pub async fn release_requester(&self, requester: &str) {
+        let mut state = self.state.lock().await;
+
+        state.requesters.remove(requester);
+
+        let released = state.profile_holds.remove_requester(requester);
+
+        if released.is_empty() {
+            return;
+        }
+
+        for (cookie, hold) in released {
+            log::info!(
+                "Releasing hold {} from {} after {} vanished",
+                cookie,
+                hold.application_id,
+                requester
+            );
+        }
+
+        self.emit(Event::ProfileHolds);
+
+        if let Err(err) = self.activate_effective_profile(&state).await {
+            log::error!(
+                "Failed to restore profile after {} vanished: {}",
+                requester,
+                err
+            );
+        }
+
+        self.emit(Event::ActiveProfile);
+    }
+
+    async fn activate_profile(&self, name: &str) -> anyhow::Result<(), zbus::fdo::Error> {
+        match self.settings().profile_by_name(&name.to_string()) {
+            Some(profile) => {
+                // Drop anything recorded outside of an activation
+                self.sysfs.take_plan();
+
+                let result = self.driver_set().activate(profile).await;
+
+                // Written before the plan is taken, so failures roll them back too
+                if result.is_ok() {
+                    self.action_set().activate(profile).await;
+                }
+                let plan = self.sysfs.take_plan();
+
+                let result = match result {
+                    Ok(()) => self.verify(name, &plan).await,
+                    Err(err) => Err(err),
+                };
+
+                // Never leave the hardware half way between two profiles
+                let result = match result {
+                    Ok(()) => Ok(()),
+                    Err(err) => {
+                        let rollback = self.sysfs.rollback(&plan).await;
+                        log::error!("Failed to activate {}: {:#}", name, err);
+                        log::warn!("{}", rollback);
+
+                        Err(anyhow::Error::new(rollback).context(err))
+                    }
+                };
+
+                self.record_plan(name, plan);
+
+                match result {
+                    Ok(()) => Ok(()),
+                    Err(err) => Err(zbus::fdo::Error::Failed(format!("{:#}", err))),
+                }
+            }
+            None => {
+                log::warn!("Received request to activate missing profile {}", name);
+
+                Err(zbus::fdo::Error::InvalidArgs("No such profile".to_string()))
+            }
+        }
+    }
+
+    /// Warn about writes the kernel did not take, in strict mode they fail the activation
+    async fn verify(&self, name: &str, plan: &[sysfs::Write]) -> anyhow::Result<()> {
+        let mismatches = self.sysfs.verify(plan).await;
+
+        for mismatch in &mismatches {
+            log::warn!(
+                "{} holds {} instead of {} after activating {}",
+                mismatch.path.display(),
+                mismatch.actual,
+                mismatch.expected,
+                name
+            );
+        }
+
+        let count = mismatches.len();
+
+        *self.mismatches.lock().unwrap() = mismatches;
+        self.emit(Event::Mismatches);
+
+        match count > 0 && self.settings().verify.strict {
+            true => Err(anyhow::anyhow!(
+                "{} attributes did not take the requested value",
+                count
+            )),
+            false => Ok(()),
+        }
+    }
+
+    fn record_plan(&self, name: &str, plan: Vec<sysfs::Write>) {
+        for write in &plan {
+            let old = write.old.as_deref().unwrap_or("?");
+
+            match self.dry_run() {
+                true => log::info!(
+                    "Would write {} to {} (was {}) for {}",
+                    write.new,
+                    write.path.display(),
+                    old,
+                    name
+                ),
+                false => log::debug!(
+                    "Wrote {} to {} (was {}) for {}",
+                    write.new,
+                    write.path.display(),
+                    old,
+                    name
+                ),
+            }
+        }
+
+        *self.plan.lock().unwrap() = plan;
+        self.emit(Event::Plan);
+    }
+
+    /// Whatever should win now: a forced profile, the strongest hold, an automatic switch, or
+    /// the user's selection. Blocked profiles give way to the default one.
+    fn effective_profile(&self, state: &State) -> String {
+        let profile = self.wanted_profile(state);
+
+        match state.blocked_profiles.contains_key(&profile) {
+            true => self.settings().default.clone(),
+            false => profile,
+        }
+    }
+
+    fn wanted_profile(&self, state: &State) -> String {
+        if let Some(profile) = self.forced_profile(state) {
+            return profile;
+        }
+
+        match state.profile_holds.effective_profile() {
+            Some(profile) => profile.to_owned(),
+            None => match (
+                state.automatic_profiles.values().next_back(),
+                &state.selected_profile,
+            ) {
+                (Some(profile), _) | (None, Some(profile)) => profile.clone(),
+                (None, None) => self.settings().default.clone(),
+            },
+        }
+    }
+
+    fn forced_profile(&self, state: &State) -> Option<String> {
+        state
+            .automatic_profiles
+            .iter()
+            .rev()
+            .find(|(trigger, _)| trigger.forces())
+            .map(|(_, profile)| profile.clone())
+    }
+
+    async fn activate_effective_profile(
+        &self,
+        state: &State,
+    ) -> anyhow::Result<(), zbus::fdo::Error> {
+        self.activate_profile(&self.effective_profile(state)).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const BOOST: &str = "sys/devices/system/cpu/cpufreq/boost";
+    const POLICY: &str = "sys/devices/system/cpu/cpufreq/policy0";
+    const PLATFORM_PROFILE: &str = "sys/firmware/acpi/platform_profile";
+
+    /// A single CPU acpi-cpufreq machine with a platform profile, running balanced
+    async fn fixture(strict: bool, dry_run: bool) -> (tempfile::TempDir, Engine) {
+        let root = tempfile::tempdir().unwrap();
+        let policy = |attribute: &str| format!("{}/{}", POLICY, attribute);
+        let attributes = [
+            ("sys/devices/system/cpu/online".to_string(), "0"),
+            (BOOST.to_string(), "1"),
+            (policy("related_cpus"), "0"),
+            (policy("cpuinfo_min_freq"), "400000"),
+            (policy("cpuinfo_max_freq"), "4000000"),
+            (policy("scaling_min_freq"), "400000"),
+            (policy("scaling_max_freq"), "4000000"),
+            (policy("scaling_driver"), "acpi-cpufreq"),
+            (policy("scaling_governor"), "powersave"),
+            (
+                policy("scaling_available_governors"),
+                "performance powersave",
+            ),
+            (policy("energy_performance_preference"), "balance_power"),
+            (
+                policy("energy_performance_available_preferences"),
+                "default performance balance_performance balance_power power",
+            ),
+            (PLATFORM_PROFILE.to_string(), "balanced"),
+            (
+                "sys/firmware/acpi/platform_profile_choices".to_string(),
+                "low-power balanced performance",
+            ),
+        ];
+
+        for (path, value) in attributes {
+            let path = root.path().join(path);
+
+            std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+            std::fs::write(path, format!("{}\n", value)).unwrap();
+        }
+
+        let config = root.path().join("config.json");
+        let profile = |boost, energy_preference, scaling_governor, platform| {
+            serde_json::json!({
+                "cpu": {
+                    "boost": boost,
+                    "energy_preference": energy_preference,
+                    "scaling_governor": scaling_governor,
+                },
+                "platform": { "profile": platform },
+            })
+        };
+
+        std::fs::write(
+            &config,
+            serde_json::json!({
+                "default": "balanced",
+                "state_file": root.path().join("selected_profile"),
+                "verify": { "strict": strict },
+                "profiles": {
+                    "balanced": profile(true, "balancePower", "powersave", "balanced"),
+                    "power-saver": profile(false, "power", "powersave", "low-power"),
+                    "performance": profile(true, "performance", "performance", "performance"),
+                },
+            })
+            .to_string(),
+        )
+        .unwrap();
+
+        let config = config.to_string_lossy().into_owned();
+        let settings = Settings::build(&config).unwrap();
+        let sysfs = Sysfs::new(root.path(), dry_run);
+        let driver_set = drivers::probe(&settings, &sysfs).await.unwrap();
+        let engine = Engine::new(
+            driver_set,
+            actions::ActionSet::default(),
+            settings,
+            config,
+            sysfs,
+        );
+
+        (root, engine)
+    }
+
+    fn read(root: &tempfile::TempDir, path: &str) -> String {
+        std::fs::read_to_string(root.path().join(path))
+            .unwrap()
+            .trim()
+            .to_owned()
+    }
+
+    #[async_std::test]
+    async fn activation_writes_every_driver() {
+        let (root, engine) = fixture(false, false).await;
+
+        engine.activate_profile("performance").await.unwrap();
+
+        assert_eq!(read(&root, BOOST), "1");
+        assert_eq!(
+            read(&root, &format!("{}/scaling_governor", POLICY)),
+            "performance"
+        );
+        assert_eq!(
+            read(&root, &format!("{}/energy_performance_preference", POLICY)),
+            "performance"
+        );
+        assert_eq!(read(&root, PLATFORM_PROFILE), "performance");
+        assert!(engine.plan().iter().all(|write| write.error.is_none()));
+    }
+
+    #[async_std::test]
+    async fn failed_activation_rolls_back_the_other_drivers() {
+        let (root, engine) = fixture(false, false).await;
+
+        // Writing to a directory fails even as root
+        std::fs::remove_file(root.path().join(PLATFORM_PROFILE)).unwrap();
+        std::fs::create_dir(root.path().join(PLATFORM_PROFILE)).unwrap();
+
+        let message = match engine.activate_profile("performance").await {
+            Err(zbus::fdo::Error::Failed(message)) => message,
+            result => panic!("Unexpected result {:?}", result),
+        };
+
+        assert_eq!(read(&root, BOOST), "1");
+        assert_eq!(
+            read(&root, &format!("{}/scaling_governor", POLICY)),
+            "powersave"
+        );
+        assert_eq!(
+            read(&root, &format!("{}/energy_performance_preference", POLICY)),
+            "balance_power"
+        );
+
+        // Reported on a single line, without a backtrace
+        assert!(message.contains("Profile activation rolled back"));
+        assert!(message.contains("not reverted: []"));
+        assert!(!message.contains('\n'));
+
+        let plan = engine.plan();
+
+        assert!(plan
+            .iter()
+            .any(|write| write.error.is_none() && write.new == "performance"));
+        assert!(plan
+            .iter()
+            .any(|write| write.error.is_some() && write.path.ends_with(PLATFORM_PROFILE)));
+    }
+
+    #[async_std::test]
+    async fn low_battery_is_listed_while_in_use() {
+        let (_root, engine) = fixture(false, false).await;
+
+        assert!(!engine
+            .actions()
+            .await
+            .contains(&LOW_BATTERY_ACTION.to_string()));
+
+        engine
+            .set_automatic_profile(Trigger::LowBattery, Some("power-saver".to_string()))
+            .await
+            .unwrap();
+        assert!(engine
+            .actions()
+            .await
+            .contains(&LOW_BATTERY_ACTION.to_string()));
+
+        engine
+            .set_automatic_profile(Trigger::LowBattery, None)
+            .await
+            .unwrap();
+        assert!(!engine
+            .actions()
+            .await
+            .contains(&LOW_BATTERY_ACTION.to_string()));
+    }
+
+    async fn effective(engine: &Engine) -> String {
+        engine.effective_profile(&*engine.state.lock().await)
+    }
+
+    #[async_std::test]
+    async fn selection_beats_automatic_profiles() {
+        let (root, engine) = fixture(false, false).await;
+
+        engine
+            .set_automatic_profile(Trigger::PowerSource, Some("power-saver".to_string()))
+            .await
+            .unwrap();
+        assert_eq!(effective(&engine).await, "power-saver");
+
+        engine
+            .set_active_profile("performance".to_string())
+            .await
+            .unwrap();
+        assert_eq!(effective(&engine).await, "performance");
+        assert_eq!(read(&root, PLATFORM_PROFILE), "performance");
+
+        // The next transition switches again
+        engine
+            .set_automatic_profile(Trigger::PowerSource, Some("balanced".to_string()))
+            .await
+            .unwrap();
+        assert_eq!(effective(&engine).await, "balanced");
+    }
+
+    #[async_std::test]
+    async fn holds_beat_automatic_profiles() {
+        let (root, engine) = fixture(false, false).await;
+
+        engine
+            .set_automatic_profile(Trigger::LowBattery, Some("power-saver".to_string()))
+            .await
+            .unwrap();
+
+        let cookie = engine
+            .hold_profile("performance", "testing", "org.example.App", ":1.1")
+            .await
+            .unwrap();
+        assert_eq!(effective(&engine).await, "performance");
+        assert_eq!(read(&root, PLATFORM_PROFILE), "performance");
+
+        engine.release_profile(cookie).await.unwrap();
+        assert_eq!(effective(&engine).await, "power-saver");
+        assert_eq!(read(&root, PLATFORM_PROFILE), "low-power");
+    }
+
+    async fn automatic(engine: &Engine, trigger: Trigger, profile: Option<&str>) {
+        engine
+            .set_automatic_profile(trigger, profile.map(str::to_string))
+            .await
+            .unwrap();
+    }
+
+    #[async_std::test]
+    async fn thermal_beats_low_battery_beats_power_source() {
+        let (_root, engine) = fixture(false, false).await;
+
+        automatic(&engine, Trigger::PowerSource, Some("balanced")).await;
+        automatic(&engine, Trigger::LowBattery, Some("power-saver")).await;
+        assert_eq!(effective(&engine).await, "power-saver");
+
+        automatic(&engine, Trigger::Thermal, Some("performance")).await;
+        assert_eq!(effective(&engine).await, "performance");
+
+        // A forced profile wins over holds and the user's choice, which apply once it lifts
+        engine
+            .hold_profile("power-saver", "testing", "org.example.App", ":1.1")
+            .await
+            .unwrap();
+        assert_eq!(effective(&engine).await, "performance");
+
+        engine
+            .set_active_profile("balanced".to_string())
+            .await
+            .unwrap();
+        assert_eq!(effective(&engine).await, "performance");
+
+        automatic(&engine, Trigger::Thermal, None).await;
+        assert_eq!(effective(&engine).await, "balanced");
+    }
+
+    /// Every file below a directory with its content
+    fn snapshot(directory: &std::path::Path) -> BTreeMap<std::path::PathBuf, Vec<u8>> {
+        let mut files = BTreeMap::new();
+
+        for entry in std::fs::read_dir(directory).unwrap() {
+            let path = entry.unwrap().path();
+
+            match path.is_dir() {
+                true => files.extend(snapshot(&path)),
+                false => {
+                    files.insert(path.clone(), std::fs::read(&path).unwrap());
+                }
+            }
+        }
+
+        files
+    }
+
+    #[async_std::test]
+    async fn dry_run_only_records_the_plan() {
+        let (root, engine) = fixture(false, true).await;
+        let before = snapshot(root.path());
+
+        engine.activate_profile("performance").await.unwrap();
+
+        assert_eq!(snapshot(root.path()), before);
+
+        let plan: Vec<_> = engine
+            .plan()
+            .into_iter()
+            .map(|write| {
+                let path = write.path.strip_prefix(root.path()).unwrap().to_owned();
+
+                (path, write.old, write.new)
+            })
+            .collect();
+        let planned = |path: &str, old: &str, new: &str| {
+            (
+                std::path::PathBuf::from(path),
+                Some(old.to_string()),
+                new.to_string(),
+            )
+        };
+
+        assert!(plan.contains(&planned(BOOST, "1", "1")));
+        assert!(plan.contains(&planned(
+            &format!("{}/scaling_governor", POLICY),
+            "powersave",
+            "performance"
+        )));
+        assert!(plan.contains(&planned(
+            &format!("{}/energy_performance_preference", POLICY),
+            "balance_power",
+            "performance"
+        )));
+        assert!(plan.contains(&planned(PLATFORM_PROFILE, "balanced", "performance")));
+    }
+
+    /// Model an attribute the kernel ignores writes to
+    fn ignore_writes(root: &tempfile::TempDir, path: &str) {
+        std::fs::remove_file(root.path().join(path)).unwrap();
+        std::os::unix::fs::symlink("/dev/null", root.path().join(path)).unwrap();
+    }
+
+    #[async_std::test]
+    async fn ignored_writes_are_reported() {
+        let (root, engine) = fixture(false, false).await;
+
+        ignore_writes(&root, BOOST);
+
+        engine.activate_profile("power-saver").await.unwrap();
+
+        let mismatches = engine.mismatches();
+
+        assert_eq!(mismatches.len(), 1);
+        assert!(mismatches[0].path.ends_with(BOOST));
+        assert_eq!(mismatches[0].expected, "0");
+        assert_eq!(mismatches[0].actual, "");
+
+        // Everything else stays applied
+        assert_eq!(read(&root, PLATFORM_PROFILE), "low-power");
+    }
+
+    #[async_std::test]
+    async fn ignored_writes_fail_strict_activation() {
+        let (root, engine) = fixture(true, false).await;
+
+        ignore_writes(&root, BOOST);
+
+        let message = match engine.activate_profile("power-saver").await {
+            Err(zbus::fdo::Error::Failed(message)) => message,
+            result => panic!("Unexpected result {:?}", result),
+        };
+
+        assert!(message.contains("1 attributes did not take the requested value"));
+        assert_eq!(engine.mismatches().len(), 1);
+        assert_eq!(read(&root, PLATFORM_PROFILE), "balanced");
+        assert_eq!(
+            read(&root, &format!("{}/energy_performance_preference", POLICY)),
+            "balance_power"
+        );
+    }
+}
@@ -0,0 +1,194 @@
+use std::collections::BTreeMap;
+
+use crate::types::PowerProfileHold;
+
+pub(crate) const PERFORMANCE: &str = "performance";
+pub(crate) const POWER_SAVER: &str = "power-saver";
+
+#[derive(Clone, Debug)]
+struct Entry {
+    hold: PowerProfileHold,
+    /// Unique bus name of the peer that asked for the hold
+    requester: String,
+}
+
+/// Reference-counted profile holds, keyed by the cookie handed out to the requester.
+#[derive(Clone, Debug, Default)]
+pub(crate) struct ProfileHolds {
+    next_cookie: u32,
+    holds: BTreeMap<u32, Entry>,
+}
+
+impl ProfileHolds {
+    /// Only performance and power-saver can be held, matching upstream.
+    pub fn holdable(profile: &str) -> bool {
+        profile == PERFORMANCE || profile == POWER_SAVER
+    }
+
+    pub fn insert(&mut self, hold: PowerProfileHold, requester: String) -> u32 {
+        // Cookies are never reused, 0 is skipped so it can't be confused with "no cookie"
+        self.next_cookie = self.next_cookie.wrapping_add(1).max(1);
+
+        while self.holds.contains_key(&self.next_cookie) {
+            self.next_cookie = self.next_cookie.wrapping_add(1).max(1);
+        }
+
+        self.holds
+            .insert(self.next_cookie, Entry { hold, requester });
+        self.next_cookie
+    }
+
+    pub fn remove(&mut self, cookie: u32) -> Option<(PowerProfileHold, String)> {
+        self.holds
+            .remove(&cookie)
+            .map(|entry| (entry.hold, entry.requester))
+    }
+
+    /// Remove every hold, returning the cookie, hold and requester of each
+    pub fn drain(&mut self) -> Vec<(u32, PowerProfileHold, String)> {
+        std::mem::take(&mut self.holds)
+            .into_iter()
+            .map(|(cookie, entry)| (cookie, entry.hold, entry.requester))
+            .collect()
+    }
+
+    /// Remove every hold taken by the given peer
+    pub fn remove_requester(&mut self, requester: &str) -> Vec<(u32, PowerProfileHold)> {
+        let cookies: Vec<u32> = self
+            .holds
+            .iter()
+            .filter(|(_, entry)| entry.requester == requester)
+            .map(|(cookie, _)| *cookie)
+            .collect();
+
+        cookies
+            .into_iter()
+            .filter_map(|cookie| self.holds.remove(&cookie).map(|entry| (cookie, entry.hold)))
+            .collect()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.holds.is_empty()
+    }
+
+    pub fn values(&self) -> Vec<PowerProfileHold> {
+        self.holds
+            .values()
+            .map(|entry| entry.hold.clone())
+            .collect()
+    }
+
+    /// Every hold with its cookie, oldest first
+    pub fn iter(&self) -> impl Iterator<Item = (u32, &PowerProfileHold)> {
+        self.holds
+            .iter()
+            .map(|(cookie, entry)| (*cookie, &entry.hold))
+    }
+
+    /// The profile that wins among the current holds, performance beats power-saver.
+    pub fn effective_profile(&self) -> Option<&str> {
+        self.holds
+            .values()
+            .map(|entry| entry.hold.profile.as_str())
+            .reduce(|winner, profile| match profile {
+                PERFORMANCE => PERFORMANCE,
+                _ => winner,
+            })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn hold(profile: &str) -> PowerProfileHold {
+        PowerProfileHold::new(
+            "org.example.App".to_string(),
+            profile.to_string(),
+            "testing".to_string(),
+        )
+    }
+
+    #[test]
+    fn cookies_start_at_one_and_increase() {
+        let mut holds = ProfileHolds::default();
+
+        assert_eq!(holds.insert(hold(PERFORMANCE), ":1.1".to_string()), 1);
+        assert_eq!(holds.insert(hold(POWER_SAVER), ":1.1".to_string()), 2);
+    }
+
+    #[test]
+    fn cookies_skip_zero_on_wrap() {
+        let mut holds = ProfileHolds {
+            next_cookie: u32::MAX,
+            ..Default::default()
+        };
+
+        assert_eq!(holds.insert(hold(PERFORMANCE), ":1.1".to_string()), 1);
+    }
+
+    #[test]
+    fn cookies_skip_live_ones() {
+        let mut holds = ProfileHolds::default();
+
+        assert_eq!(holds.insert(hold(PERFORMANCE), ":1.1".to_string()), 1);
+        assert_eq!(holds.insert(hold(PERFORMANCE), ":1.1".to_string()), 2);
+
+        // Wrap around onto cookies that are still held
+        holds.next_cookie = u32::MAX;
+
+        assert_eq!(holds.insert(hold(POWER_SAVER), ":1.2".to_string()), 3);
+    }
+
+    #[test]
+    fn cookies_are_not_reused_after_release() {
+        let mut holds = ProfileHolds::default();
+
+        let cookie = holds.insert(hold(PERFORMANCE), ":1.1".to_string());
+        holds.remove(cookie);
+
+        assert_ne!(holds.insert(hold(PERFORMANCE), ":1.1".to_string()), cookie);
+    }
+
+    #[test]
+    fn no_holds_no_profile() {
+        assert_eq!(ProfileHolds::default().effective_profile(), None);
+    }
+
+    #[test]
+    fn performance_beats_power_saver() {
+        for order in [[PERFORMANCE, POWER_SAVER], [POWER_SAVER, PERFORMANCE]] {
+            let mut holds = ProfileHolds::default();
+
+            for profile in order {
+                holds.insert(hold(profile), ":1.1".to_string());
+            }
+
+            assert_eq!(holds.effective_profile(), Some(PERFORMANCE));
+        }
+    }
+
+    #[test]
+    fn power_saver_wins_once_performance_is_released() {
+        let mut holds = ProfileHolds::default();
+
+        holds.insert(hold(POWER_SAVER), ":1.1".to_string());
+        let cookie = holds.insert(hold(PERFORMANCE), ":1.2".to_string());
+        holds.remove(cookie);
+
+        assert_eq!(holds.effective_profile(), Some(POWER_SAVER));
+    }
+
+    #[test]
+    fn remove_requester_only_drops_its_holds() {
+        let mut holds = ProfileHolds::default();
+
+        holds.insert(hold(PERFORMANCE), ":1.1".to_string());
+        holds.insert(hold(POWER_SAVER), ":1.2".to_string());
+
+        let released = holds.remove_requester(":1.1");
+
+        assert_eq!(released.len(), 1);
+        assert_eq!(holds.effective_profile(), Some(POWER_SAVER));
+    }
+}
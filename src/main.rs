@@ -1,20 +1,28 @@
 use std::future::pending;
 
 use anyhow::Result;
-use clap::Parser;
+use clap::{Parser, Subcommand};
 use zbus::connection;
 
+mod actions;
+mod check;
+mod client;
 mod dbus;
 mod drivers;
+mod engine;
+mod holds;
+mod monitors;
+mod persist;
 mod settings;
+mod sysfs;
 mod types;
 
 /// Drop in replacement for power-profiles-daemon
 #[derive(Parser, Debug)]
 #[command(version, about, long_about = None)]
 struct Args {
-    /// Path to config file
-    #[arg(short, long, default_value = "config.json")]
+    /// Path to config file, fragments in the conf.d directory next to it override it
+    #[arg(short, long, default_value = "config.json", global = true)]
     config: String,
 
     /// Best effort to avoid mutable operations
@@ -32,21 +40,79 @@ struct Args {
     /// Launch on the user session bus (useful for development)
     #[arg(long, default_value_t = false)]
     user: bool,
+
+    /// Check the config or act as a client of a running daemon instead
+    #[command(subcommand)]
+    command: Option<Mode>,
+
+    /// Resolve sysfs paths against this directory instead of / (overrides the config file)
+    #[arg(long, global = true)]
+    sysfs_root: Option<String>,
+}
+
+#[derive(Subcommand, Debug)]
+enum Mode {
+    /// Validate the profiles of the config file against this machine's hardware and exit
+    CheckConfig,
+    #[command(flatten)]
+    Client(client::Command),
 }
 
 #[async_std::main]
 async fn main() -> Result<()> {
-    env_logger::Builder::from_env(env_logger::Env::default().default_filter_or("info")).init();
-    log::info!("{} version {}", env!("CARGO_PKG_NAME"), env!("CARGO_PKG_VERSION"));
+    // Installed as powerprofilesctl, behave exactly like upstream's client
+    if std::env::args_os()
+        .next()
+        .and_then(|arg| {
+            std::path::Path::new(&arg)
+                .file_name()
+                .map(|name| name == client::CLIENT_NAME)
+        })
+        .unwrap_or(false)
+    {
+        return client::Cli::parse().run().await;
+    }
 
     let args = Args::parse();
+
+    if let Some(Mode::Client(command)) = args.command {
+        return client::run(command, args.user).await;
+    }
+
+    env_logger::Builder::from_env(env_logger::Env::default().default_filter_or("info")).init();
+    log::info!("{} version {}", env!("CARGO_PKG_NAME"), env!("CARGO_PKG_VERSION"));
     let settings = settings::Settings::build(&args.config)?;
-    let driver_set = drivers::probe(&settings).await?;
+    let sysfs = sysfs::Sysfs::new(
+        args.sysfs_root
+            .as_deref()
+            .unwrap_or(settings.sysfs_root.as_str()),
+        args.dry_run,
+    );
+    let driver_set = drivers::probe(&settings, &sysfs).await?;
 
     log::trace!("Loaded {:#?}", settings);
 
-    let handler = dbus::Handler::new(driver_set.clone(), settings.clone());
-    let legacy_handler = dbus::legacy::Handler::new(driver_set, settings);
+    if let Some(Mode::CheckConfig) = args.command {
+        if !check::run(&settings, &driver_set).await? {
+            std::process::exit(1);
+        }
+
+        return Ok(());
+    }
+
+    if args.dry_run {
+        log::info!("Running in dry-run mode, no changes will be made");
+    }
+
+    let action_set = actions::probe(&settings, &sysfs).await;
+    let engine = engine::Engine::new(driver_set, action_set, settings, args.config, sysfs);
+
+    if let Err(err) = engine.restore().await {
+        log::error!("Failed to restore profile: {:?}", err);
+    }
+
+    let handler = dbus::Handler::new(engine.clone());
+    let legacy_handler = dbus::legacy::Handler::new(engine.clone());
 
     let mut bus_type = connection::Builder::system
         as fn() -> Result<zbus::ConnectionBuilder<'static>, zbus::Error>;
@@ -64,26 +130,66 @@ async fn main() -> Result<()> {
     if !args.disable_upower {
         log::info!("Starting upower interface handler");
 
-        connections.push(
-            bus_type()?
-                .name("org.freedesktop.UPower.PowerProfiles")?
-                .serve_at("/org/freedesktop/UPower/PowerProfiles", handler)?
-                .build()
-                .await?,
-        );
+        let connection = bus_type()?
+            .name("org.freedesktop.UPower.PowerProfiles")?
+            .serve_at("/org/freedesktop/UPower/PowerProfiles", handler)?
+            .serve_at(
+                "/org/freedesktop/UPower/PowerProfiles",
+                dbus::extension::Handler::new(engine.clone()),
+            )?
+            .build()
+            .await?;
+
+        let iface = connection
+            .object_server()
+            .interface::<_, dbus::Handler>("/org/freedesktop/UPower/PowerProfiles")
+            .await?;
+        async_std::task::spawn(dbus::forward_events(iface, engine.subscribe()));
+
+        let iface = connection
+            .object_server()
+            .interface::<_, dbus::extension::Handler>("/org/freedesktop/UPower/PowerProfiles")
+            .await?;
+        async_std::task::spawn(dbus::forward_events(iface, engine.subscribe()));
+
+        connections.push(connection);
     }
 
     if !args.disable_legacy {
         log::info!("Starting legacy interface handler");
 
-        connections.push(
-            bus_type()?
-                .name("net.hadess.PowerProfiles")?
-                .serve_at("/net/hadess/PowerProfiles", legacy_handler)?
-                .build()
-                .await?,
-        );
+        let connection = bus_type()?
+            .name("net.hadess.PowerProfiles")?
+            .serve_at("/net/hadess/PowerProfiles", legacy_handler)?
+            .serve_at(
+                "/net/hadess/PowerProfiles",
+                dbus::extension::Handler::new(engine.clone()),
+            )?
+            .build()
+            .await?;
+
+        let iface = connection
+            .object_server()
+            .interface::<_, dbus::legacy::Handler>("/net/hadess/PowerProfiles")
+            .await?;
+        async_std::task::spawn(dbus::forward_events(iface, engine.subscribe()));
+
+        let iface = connection
+            .object_server()
+            .interface::<_, dbus::extension::Handler>("/net/hadess/PowerProfiles")
+            .await?;
+        async_std::task::spawn(dbus::forward_events(iface, engine.subscribe()));
+
+        connections.push(connection);
     }
 
+    async_std::task::spawn(monitors::drift::run(engine.clone()));
+    async_std::task::spawn(monitors::hotplug::run(engine.clone()));
+    async_std::task::spawn(monitors::power_source::run(engine.clone()));
+    async_std::task::spawn(monitors::low_battery::run(engine.clone()));
+    async_std::task::spawn(monitors::thermal::run(engine.clone()));
+    async_std::task::spawn(monitors::lap::run(engine.clone()));
+    async_std::task::spawn(monitors::sighup::run(engine.clone()));
+
     Ok(pending::<()>().await)
 }
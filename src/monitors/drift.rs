@@ -0,0 +1,60 @@
+use std::time::Duration;
+
+use crate::{
+    engine::{Engine, Event},
+    monitors::{Toggle, IDLE_INTERVAL},
+};
+
+/// Poll the CPU settings for changes made outside the daemon, e.g. by `cpupower`.
+///
+/// sysfs attributes don't raise inotify events when the kernel changes them, so polling
+/// is the only reliable way to notice.
+pub(crate) async fn run(engine: Engine) {
+    let mut toggle = Toggle::new("Drift");
+    let mut last = None;
+
+    loop {
+        let settings = engine.settings().drift.clone();
+
+        if settings.interval == 0 {
+            toggle.update(false);
+            async_std::task::sleep(IDLE_INTERVAL).await;
+            continue;
+        }
+
+        if toggle.update(true) {
+            last = engine.inferred_profile().await.ok().flatten();
+        } else {
+            poll(&engine, settings.reapply, &mut last).await;
+        }
+
+        async_std::task::sleep(Duration::from_secs(settings.interval)).await;
+    }
+}
+
+async fn poll(engine: &Engine, reapply: bool, last: &mut Option<String>) {
+    let current = match engine.inferred_profile().await {
+        Ok(current) => current,
+        Err(err) => {
+            log::debug!("Unable to infer current profile: {}", err);
+            return;
+        }
+    };
+
+    if current == *last {
+        return;
+    }
+
+    log::info!("Profile changed from {:?} to {:?}", last, current);
+    engine.emit(Event::ActiveProfile);
+
+    *last = current;
+
+    if reapply {
+        match engine.reapply_if_drifted().await {
+            Ok(true) => *last = engine.inferred_profile().await.ok().flatten(),
+            Ok(false) => (),
+            Err(err) => log::error!("Failed to re-apply drifted profile: {}", err),
+        }
+    }
+}
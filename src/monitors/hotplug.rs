@@ -0,0 +1,174 @@
+use std::{
+    os::fd::{AsRawFd, OwnedFd},
+    time::Duration,
+};
+
+use anyhow::Result;
+use nix::errno::Errno;
+use nix::sys::socket::{
+    bind, recv, socket, AddressFamily, MsgFlags, NetlinkAddr, SockFlag, SockProtocol, SockType,
+};
+
+use crate::engine::Engine;
+
+/// Kernel uevents, as opposed to the ones re-broadcast by udev
+const KERNEL_UEVENTS: u32 = 1;
+
+/// Bringing several CPUs online at once raises one event per CPU
+const SETTLE_TIME: Duration = Duration::from_millis(500);
+
+/// Re-apply the active profile when a CPU comes online, it starts out with the kernel defaults.
+pub(crate) async fn run(engine: Engine) {
+    let socket = match open() {
+        Ok(socket) => socket,
+        Err(err) => {
+            log::warn!("CPU hotplug watcher disabled: {}", err);
+            return;
+        }
+    };
+
+    let (sender, receiver) = async_std::channel::unbounded();
+
+    // The socket is blocking, so it gets a thread of its own
+    async_std::task::spawn_blocking(move || {
+        let mut buffer = vec![0; 8192];
+
+        loop {
+            let length = match recv(socket.as_raw_fd(), &mut buffer, MsgFlags::empty()) {
+                Ok(length) => length,
+                // Interrupted by a signal, nothing was lost
+                Err(Errno::EINTR) => continue,
+                // The kernel dropped uevents while the buffer was full, later ones still arrive
+                Err(Errno::ENOBUFS) => {
+                    log::warn!("Missed uevents, the receive buffer overflowed");
+                    continue;
+                }
+                Err(err) => {
+                    log::error!("Failed to receive uevent: {}", err);
+                    return;
+                }
+            };
+
+            if let Some(devpath) = cpu_online(&buffer[..length]) {
+                if sender.send_blocking(devpath).is_err() {
+                    return;
+                }
+            }
+        }
+    });
+
+    while let Ok(devpath) = receiver.recv().await {
+        log::info!("{} came online", devpath);
+
+        async_std::task::sleep(SETTLE_TIME).await;
+
+        while let Ok(devpath) = receiver.try_recv() {
+            log::info!("{} came online", devpath);
+        }
+
+        if let Err(err) = engine.reapply().await {
+            log::error!("Failed to re-apply profile after CPU hotplug: {}", err);
+        }
+    }
+}
+
+fn open() -> Result<OwnedFd> {
+    let socket = socket(
+        AddressFamily::Netlink,
+        SockType::Datagram,
+        SockFlag::SOCK_CLOEXEC,
+        SockProtocol::NetlinkKObjectUEvent,
+    )?;
+
+    bind(socket.as_raw_fd(), &NetlinkAddr::new(0, KERNEL_UEVENTS))?;
+
+    Ok(socket)
+}
+
+/// The device path of a CPU from an "online" uevent.
+///
+/// A uevent is a header followed by NUL separated KEY=value pairs, e.g.
+/// `online@/devices/system/cpu/cpu3\0ACTION=online\0DEVPATH=/devices/system/cpu/cpu3\0...`
+fn cpu_online(message: &[u8]) -> Option<String> {
+    let message = String::from_utf8_lossy(message);
+    let mut action = None;
+    let mut devpath = None;
+    let mut subsystem = None;
+
+    for (key, value) in message
+        .split('\0')
+        .filter_map(|field| field.split_once('='))
+    {
+        match key {
+            "ACTION" => action = Some(value),
+            "DEVPATH" => devpath = Some(value),
+            "SUBSYSTEM" => subsystem = Some(value),
+            _ => (),
+        }
+    }
+
+    match (action, subsystem) {
+        (Some("online"), Some("cpu")) => devpath.map(|devpath| devpath.to_owned()),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn uevent(fields: &[&str]) -> Vec<u8> {
+        fields.join("\0").into_bytes()
+    }
+
+    #[test]
+    fn cpu_came_online() {
+        let message = uevent(&[
+            "online@/devices/system/cpu/cpu3",
+            "ACTION=online",
+            "DEVPATH=/devices/system/cpu/cpu3",
+            "SUBSYSTEM=cpu",
+            "SEQNUM=4242",
+        ]);
+
+        assert_eq!(
+            cpu_online(&message),
+            Some("/devices/system/cpu/cpu3".to_string())
+        );
+    }
+
+    #[test]
+    fn cpu_went_offline() {
+        let message = uevent(&[
+            "offline@/devices/system/cpu/cpu3",
+            "ACTION=offline",
+            "DEVPATH=/devices/system/cpu/cpu3",
+            "SUBSYSTEM=cpu",
+        ]);
+
+        assert_eq!(cpu_online(&message), None);
+    }
+
+    #[test]
+    fn other_device_came_online() {
+        let message = uevent(&[
+            "online@/devices/system/memory/memory8",
+            "ACTION=online",
+            "DEVPATH=/devices/system/memory/memory8",
+            "SUBSYSTEM=memory",
+        ]);
+
+        assert_eq!(cpu_online(&message), None);
+    }
+
+    #[test]
+    fn missing_devpath() {
+        let message = uevent(&[
+            "online@/devices/system/cpu/cpu3",
+            "ACTION=online",
+            "SUBSYSTEM=cpu",
+        ]);
+
+        assert_eq!(cpu_online(&message), None);
+    }
+}
@@ -0,0 +1,93 @@
+use std::time::Duration;
+
+use anyhow::Result;
+
+use crate::{
+    engine::Engine,
+    monitors::{Toggle, IDLE_INTERVAL},
+    sysfs::Sysfs,
+};
+
+/// Reported in PerformanceDegraded while on a lap, like upstream
+pub(crate) const LAP_DETECTED: &str = "lap-detected";
+
+/// Mark performance as degraded while the laptop sits on the user's lap, where the firmware
+/// limits it to keep the bottom cool, and block the performance profile if configured.
+pub(crate) async fn run(engine: Engine) {
+    let mut toggle = Toggle::new("Lap");
+    let mut watching = None;
+    let mut last = None;
+
+    loop {
+        let settings = engine.settings().lap.clone();
+
+        // Attributes may have been reconfigured, so they are looked up on every poll
+        let attribute = match settings.enabled && settings.interval != 0 {
+            true => attribute(engine.sysfs(), &settings.attributes).await,
+            false => None,
+        };
+
+        if attribute != watching {
+            match &attribute {
+                Some(attribute) => log::info!("Watching {} for lap mode", attribute),
+                None if settings.enabled => log::debug!("No lap mode attribute found"),
+                None => (),
+            }
+
+            watching = attribute.clone();
+        }
+
+        let Some(attribute) = attribute else {
+            if toggle.update(false) && last == Some(true) {
+                set_lap(&engine, false, false).await;
+            }
+
+            last = None;
+
+            async_std::task::sleep(IDLE_INTERVAL).await;
+            continue;
+        };
+
+        toggle.update(true);
+
+        match on_lap(engine.sysfs(), &attribute).await {
+            Ok(lap) => {
+                if last != Some(lap) {
+                    log::info!("Lap mode {}", if lap { "detected" } else { "cleared" });
+                    last = Some(lap);
+                }
+
+                // Also picks up block_performance changing on reload
+                set_lap(&engine, lap, lap && settings.block_performance).await;
+            }
+            Err(err) => log::debug!("Unable to read {}: {}", attribute, err),
+        }
+
+        async_std::task::sleep(Duration::from_secs(settings.interval)).await;
+    }
+}
+
+async fn set_lap(engine: &Engine, lap: bool, block: bool) {
+    engine.set_degraded(LAP_DETECTED, lap).await;
+
+    if let Err(err) = engine
+        .set_blocked(crate::holds::PERFORMANCE, LAP_DETECTED, block)
+        .await
+    {
+        log::error!("Failed to switch profile on lap mode change: {}", err);
+    }
+}
+
+async fn attribute(sysfs: &Sysfs, attributes: &[String]) -> Option<String> {
+    for attribute in attributes {
+        if sysfs.exists(attribute).await {
+            return Some(attribute.clone());
+        }
+    }
+
+    None
+}
+
+async fn on_lap(sysfs: &Sysfs, attribute: &str) -> Result<bool> {
+    Ok(sysfs.read(attribute).await? == "1")
+}
@@ -0,0 +1,184 @@
+use std::time::Duration;
+
+use anyhow::Result;
+
+use crate::{
+    engine::{Engine, Trigger},
+    monitors::{Toggle, IDLE_INTERVAL},
+    settings::LowBatterySettings,
+    sysfs::Sysfs,
+};
+
+const POWER_SUPPLY: &str = "/sys/class/power_supply";
+
+/// Combined charge of every battery, and whether any of them is discharging
+struct Charge {
+    capacity: u8,
+    discharging: bool,
+}
+
+/// Switch to the configured profile while the battery runs low.
+///
+/// The profile is only switched to while discharging, and switched away from once the
+/// charge is back above the higher threshold, so it doesn't flap around a single value.
+pub(crate) async fn run(engine: Engine) {
+    let mut toggle = Toggle::new("Low battery");
+    let mut low = false;
+
+    loop {
+        let settings = engine.settings().low_battery.clone();
+
+        if !settings.enabled() || settings.interval == 0 {
+            toggle.update(false);
+
+            if low {
+                match engine
+                    .set_automatic_profile(Trigger::LowBattery, None)
+                    .await
+                {
+                    Ok(()) => low = false,
+                    Err(err) => log::error!("Failed to switch profile after disabling: {}", err),
+                }
+            }
+
+            async_std::task::sleep(IDLE_INTERVAL).await;
+            continue;
+        }
+
+        toggle.update(true);
+
+        match charge(engine.sysfs()).await {
+            Ok(Some(charge)) if is_low(low, &charge, &settings) != low => {
+                match low {
+                    false => log::info!("Battery low at {}%", charge.capacity),
+                    true => log::info!("Battery recovered to {}%", charge.capacity),
+                }
+
+                let profile = if low { None } else { settings.profile.clone() };
+
+                match engine
+                    .set_automatic_profile(Trigger::LowBattery, profile)
+                    .await
+                {
+                    Ok(()) => low = !low,
+                    Err(err) => log::error!("Failed to switch profile on battery change: {}", err),
+                }
+            }
+            Ok(..) => (),
+            Err(err) => log::debug!("Unable to read the battery charge: {}", err),
+        }
+
+        async_std::task::sleep(Duration::from_secs(settings.interval)).await;
+    }
+}
+
+/// Whether the battery counts as low now, given whether it did on the last poll
+fn is_low(low: bool, charge: &Charge, settings: &LowBatterySettings) -> bool {
+    match low {
+        false => charge.discharging && charge.capacity < settings.below,
+        true => charge.capacity <= settings.above,
+    }
+}
+
+/// `None` on machines without a battery
+async fn charge(sysfs: &Sysfs) -> Result<Option<Charge>> {
+    let mut capacities = Vec::new();
+    let mut discharging = false;
+
+    for battery in sysfs.list(POWER_SUPPLY, "BAT").await? {
+        let battery = format!("{}/{}", POWER_SUPPLY, battery);
+
+        capacities.push(
+            sysfs
+                .read(format!("{}/capacity", battery))
+                .await?
+                .parse::<u32>()?,
+        );
+        discharging |= sysfs.read(format!("{}/status", battery)).await? == "Discharging";
+    }
+
+    if capacities.is_empty() {
+        return Ok(None);
+    }
+
+    Ok(Some(Charge {
+        capacity: (capacities.iter().sum::<u32>() / capacities.len() as u32) as u8,
+        discharging,
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sysfs::tests::fixture;
+
+    fn settings() -> LowBatterySettings {
+        LowBatterySettings {
+            profile: Some("power-saver".to_string()),
+            ..Default::default()
+        }
+    }
+
+    fn at(capacity: u8, discharging: bool) -> Charge {
+        Charge {
+            capacity,
+            discharging,
+        }
+    }
+
+    #[test]
+    fn low_below_the_lower_threshold_while_discharging() {
+        let settings = settings();
+
+        assert!(!is_low(false, &at(20, true), &settings));
+        assert!(is_low(false, &at(19, true), &settings));
+        assert!(!is_low(false, &at(19, false), &settings));
+    }
+
+    #[test]
+    fn recovers_above_the_higher_threshold_only() {
+        let settings = settings();
+
+        // Charging alone doesn't recover, nor does crossing back over the lower threshold
+        assert!(is_low(true, &at(19, false), &settings));
+        assert!(is_low(true, &at(25, true), &settings));
+        assert!(is_low(true, &at(30, false), &settings));
+        assert!(!is_low(true, &at(31, false), &settings));
+        assert!(!is_low(true, &at(31, true), &settings));
+    }
+
+    #[async_std::test]
+    async fn charge_averages_every_battery() {
+        let (_root, sysfs) = fixture(&[
+            ("sys/class/power_supply/AC/online", "0\n"),
+            ("sys/class/power_supply/BAT0/capacity", "40\n"),
+            ("sys/class/power_supply/BAT0/status", "Discharging\n"),
+            ("sys/class/power_supply/BAT1/capacity", "81\n"),
+            ("sys/class/power_supply/BAT1/status", "Full\n"),
+        ]);
+
+        let charge = charge(&sysfs).await.unwrap().unwrap();
+
+        assert_eq!(charge.capacity, 60);
+        assert!(charge.discharging);
+    }
+
+    #[async_std::test]
+    async fn charge_is_not_discharging_unless_a_battery_is() {
+        let (_root, sysfs) = fixture(&[
+            ("sys/class/power_supply/BAT0/capacity", "40\n"),
+            ("sys/class/power_supply/BAT0/status", "Charging\n"),
+            ("sys/class/power_supply/BAT1/capacity", "100\n"),
+            ("sys/class/power_supply/BAT1/status", "Full\n"),
+        ]);
+
+        assert!(!charge(&sysfs).await.unwrap().unwrap().discharging);
+    }
+
+    #[async_std::test]
+    async fn charge_without_batteries() {
+        let (_root, sysfs) = fixture(&[("sys/class/power_supply/AC/online", "1\n")]);
+
+        assert!(charge(&sysfs).await.unwrap().is_none());
+    }
+}
@@ -0,0 +1,44 @@
+use std::time::Duration;
+
+pub(crate) mod drift;
+pub(crate) mod hotplug;
+pub(crate) mod lap;
+pub(crate) mod low_battery;
+pub(crate) mod power_source;
+pub(crate) mod sighup;
+pub(crate) mod thermal;
+
+/// How often a disabled watcher checks whether a reload enabled it
+pub(crate) const IDLE_INTERVAL: Duration = Duration::from_secs(5);
+
+/// Whether a watcher is enabled, re-evaluated on every poll since a reload can toggle it
+pub(crate) struct Toggle {
+    name: &'static str,
+    enabled: Option<bool>,
+}
+
+impl Toggle {
+    pub fn new(name: &'static str) -> Self {
+        Self {
+            name,
+            enabled: None,
+        }
+    }
+
+    /// Record whether the watcher is enabled now, returns true if that changed
+    pub fn update(&mut self, enabled: bool) -> bool {
+        if self.enabled == Some(enabled) {
+            return false;
+        }
+
+        log::info!(
+            "{} watcher {}",
+            self.name,
+            if enabled { "enabled" } else { "disabled" }
+        );
+
+        self.enabled = Some(enabled);
+
+        true
+    }
+}
@@ -0,0 +1,186 @@
+use std::time::Duration;
+
+use anyhow::Result;
+
+use crate::{
+    engine::{Engine, Trigger},
+    monitors::{Toggle, IDLE_INTERVAL},
+    sysfs::Sysfs,
+};
+
+const POWER_SUPPLY: &str = "/sys/class/power_supply";
+
+/// Supply types that power the machine when online, batteries report "Battery"
+const EXTERNAL_SUPPLIES: [&str; 3] = ["Mains", "USB", "Wireless"];
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub(crate) enum PowerSource {
+    Ac,
+    Battery,
+}
+
+/// Switch profiles when the machine is plugged in or unplugged, as configured.
+///
+/// Only transitions switch, so a profile the user selects in between sticks until the
+/// next one. That includes the one restored at startup, the source found then is not a
+/// transition.
+pub(crate) async fn run(engine: Engine) {
+    let mut toggle = Toggle::new("Power source");
+    let mut last = None;
+
+    loop {
+        let settings = engine.settings().power_source.clone();
+
+        if !settings.enabled() || settings.interval == 0 {
+            if toggle.update(false) {
+                last = None;
+
+                if let Err(err) = engine
+                    .set_automatic_profile(Trigger::PowerSource, None)
+                    .await
+                {
+                    log::error!("Failed to switch profile after disabling: {}", err);
+                }
+            }
+
+            async_std::task::sleep(IDLE_INTERVAL).await;
+            continue;
+        }
+
+        if toggle.update(true) {
+            last = seed(engine.sysfs()).await;
+        } else {
+            poll(&engine, &mut last).await;
+        }
+
+        async_std::task::sleep(Duration::from_secs(settings.interval)).await;
+    }
+}
+
+/// The source when the watcher starts, which is not a transition
+async fn seed(sysfs: &Sysfs) -> Option<PowerSource> {
+    match current(sysfs).await {
+        Ok(source) => {
+            if let Some(source) = source {
+                log::info!("Running on {:?} power", source);
+            }
+
+            source
+        }
+        Err(err) => {
+            log::debug!("Unable to determine the power source: {}", err);
+            None
+        }
+    }
+}
+
+async fn poll(engine: &Engine, last: &mut Option<PowerSource>) {
+    match current(engine.sysfs()).await {
+        Ok(Some(source)) if *last != Some(source) => {
+            log::info!("Running on {:?} power", source);
+
+            let settings = engine.settings();
+            let profile = match source {
+                PowerSource::Ac => settings.power_source.ac.clone(),
+                PowerSource::Battery => settings.power_source.battery.clone(),
+            };
+
+            match engine
+                .set_automatic_profile(Trigger::PowerSource, profile)
+                .await
+            {
+                Ok(()) => *last = Some(source),
+                Err(err) => log::error!("Failed to switch profile on {:?}: {}", source, err),
+            }
+        }
+        Ok(..) => (),
+        Err(err) => log::debug!("Unable to determine the power source: {}", err),
+    }
+}
+
+/// `None` on machines without an external supply, e.g. desktops that only report a UPS
+async fn current(sysfs: &Sysfs) -> Result<Option<PowerSource>> {
+    let mut source = None;
+
+    for supply in sysfs.list(POWER_SUPPLY, "").await? {
+        let supply = format!("{}/{}", POWER_SUPPLY, supply);
+
+        // Some drivers, e.g. for peripheral batteries, leave out attributes
+        let kind = match sysfs.read(format!("{}/type", supply)).await {
+            Ok(kind) => kind,
+            Err(err) => {
+                log::debug!("Skipping supply: {}", err);
+                continue;
+            }
+        };
+
+        if !EXTERNAL_SUPPLIES.contains(&kind.as_str()) {
+            continue;
+        }
+
+        // USB ports are listed whether something is plugged in or not
+        match sysfs.read(format!("{}/online", supply)).await.as_deref() {
+            Ok("1") => return Ok(Some(PowerSource::Ac)),
+            Ok(..) => source = Some(PowerSource::Battery),
+            Err(err) => log::debug!("Skipping supply: {}", err),
+        }
+    }
+
+    Ok(source)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sysfs::tests::fixture;
+
+    #[async_std::test]
+    async fn online_supply_means_ac() {
+        let (_root, sysfs) = fixture(&[
+            ("sys/class/power_supply/BAT0/type", "Battery\n"),
+            (
+                "sys/class/power_supply/ucsi-source-psy-USBC000:001/type",
+                "USB\n",
+            ),
+            (
+                "sys/class/power_supply/ucsi-source-psy-USBC000:001/online",
+                "0\n",
+            ),
+            ("sys/class/power_supply/AC/type", "Mains\n"),
+            ("sys/class/power_supply/AC/online", "1\n"),
+        ]);
+
+        assert_eq!(current(&sysfs).await.unwrap(), Some(PowerSource::Ac));
+    }
+
+    #[async_std::test]
+    async fn offline_supplies_mean_battery() {
+        let (_root, sysfs) = fixture(&[
+            ("sys/class/power_supply/BAT0/type", "Battery\n"),
+            ("sys/class/power_supply/AC/type", "Mains\n"),
+            ("sys/class/power_supply/AC/online", "0\n"),
+        ]);
+
+        assert_eq!(current(&sysfs).await.unwrap(), Some(PowerSource::Battery));
+    }
+
+    #[async_std::test]
+    async fn supplies_without_attributes_are_skipped() {
+        let (_root, sysfs) = fixture(&[
+            // Without a type, like some HID++ batteries, or without online
+            ("sys/class/power_supply/0_hidpp_battery_0/capacity", "80\n"),
+            ("sys/class/power_supply/1_broken/type", "Mains\n"),
+            ("sys/class/power_supply/AC/type", "Mains\n"),
+            ("sys/class/power_supply/AC/online", "1\n"),
+        ]);
+
+        assert_eq!(current(&sysfs).await.unwrap(), Some(PowerSource::Ac));
+    }
+
+    #[async_std::test]
+    async fn no_external_supply() {
+        let (_root, sysfs) = fixture(&[("sys/class/power_supply/BAT0/type", "Battery\n")]);
+
+        assert_eq!(current(&sysfs).await.unwrap(), None);
+    }
+}
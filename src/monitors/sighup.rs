@@ -0,0 +1,65 @@
+use std::{
+    os::fd::IntoRawFd,
+    sync::atomic::{AtomicI32, Ordering},
+};
+
+use anyhow::Result;
+use async_std::{io::ReadExt, os::unix::net::UnixStream};
+use nix::sys::signal::{self, SigHandler, Signal};
+
+use crate::engine::Engine;
+
+/// Write end of the pipe the signal handler wakes the reload task through
+static WAKER: AtomicI32 = AtomicI32::new(-1);
+
+extern "C" fn wake(_signum: nix::libc::c_int) {
+    let waker = WAKER.load(Ordering::SeqCst);
+
+    if waker >= 0 {
+        // SAFETY: write is async-signal-safe, a full pipe already has a wakeup queued
+        unsafe {
+            nix::libc::write(waker, [0u8].as_ptr().cast(), 1);
+        }
+    }
+}
+
+/// Reload the config file on SIGHUP, like most daemons do
+pub(crate) async fn run(engine: Engine) {
+    let mut receiver = match listen() {
+        Ok(receiver) => receiver,
+        Err(err) => {
+            log::warn!("Reloading on SIGHUP disabled: {}", err);
+            return;
+        }
+    };
+
+    let mut buffer = [0; 64];
+
+    // Several signals in a row are read at once, and trigger a single reload
+    while let Ok(length) = receiver.read(&mut buffer).await {
+        if length == 0 {
+            return;
+        }
+
+        log::info!("Received SIGHUP, reloading settings");
+
+        if let Err(err) = engine.reload().await {
+            log::error!(
+                "Failed to reload settings, keeping the current ones: {:#}",
+                err
+            );
+        }
+    }
+}
+
+fn listen() -> Result<UnixStream> {
+    let (receiver, sender) = std::os::unix::net::UnixStream::pair()?;
+
+    sender.set_nonblocking(true)?;
+    WAKER.store(sender.into_raw_fd(), Ordering::SeqCst);
+
+    // SAFETY: the handler only touches an atomic and calls write
+    unsafe { signal::signal(Signal::SIGHUP, SigHandler::Handler(wake)) }?;
+
+    Ok(receiver.into())
+}
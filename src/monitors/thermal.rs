@@ -0,0 +1,286 @@
+use std::time::Duration;
+
+use anyhow::Result;
+
+use crate::{
+    engine::{Engine, Trigger},
+    monitors::{Toggle, IDLE_INTERVAL},
+    settings::ThermalSettings,
+    sysfs::Sysfs,
+};
+
+const THERMAL: &str = "/sys/class/thermal";
+
+/// Reported in PerformanceDegraded while a zone runs hot, like upstream
+pub(crate) const HIGH_TEMPERATURE: &str = "high-operating-temperature";
+
+/// A thermal zone, its temperature and the one it is considered hot above, in millidegrees
+struct Zone {
+    name: String,
+    temperature: i64,
+    threshold: i64,
+}
+
+/// Mark performance as degraded while a thermal zone is hot, and force the configured
+/// profile until every zone cooled down again.
+pub(crate) async fn run(engine: Engine) {
+    let mut toggle = Toggle::new("Thermal");
+    let mut hot = false;
+
+    loop {
+        let settings = engine.settings().thermal.clone();
+
+        if !settings.enabled || settings.interval == 0 {
+            if toggle.update(false) && hot {
+                hot = false;
+                transition(&engine, &settings, hot).await;
+            }
+
+            async_std::task::sleep(IDLE_INTERVAL).await;
+            continue;
+        }
+
+        toggle.update(true);
+
+        match zones(engine.sysfs(), &settings).await {
+            Ok(zones) if is_hot(hot, &zones, &settings) != hot => {
+                hot = !hot;
+
+                match zones.iter().find(|zone| zone.temperature >= zone.threshold) {
+                    Some(zone) if hot => log::warn!(
+                        "{} at {}°C, hot above {}°C",
+                        zone.name,
+                        zone.temperature / 1000,
+                        zone.threshold / 1000
+                    ),
+                    _ => log::info!("Every thermal zone cooled down"),
+                }
+
+                transition(&engine, &settings, hot).await;
+            }
+            Ok(..) => (),
+            Err(err) => log::debug!("Unable to read thermal zones: {}", err),
+        }
+
+        async_std::task::sleep(Duration::from_secs(settings.interval)).await;
+    }
+}
+
+/// Whether the zones count as hot now, given whether they did on the last poll
+fn is_hot(hot: bool, zones: &[Zone], settings: &ThermalSettings) -> bool {
+    let hysteresis = i64::from(settings.hysteresis) * 1000;
+
+    match hot {
+        false => zones.iter().any(|zone| zone.temperature >= zone.threshold),
+        true => zones
+            .iter()
+            .any(|zone| zone.temperature >= zone.threshold - hysteresis),
+    }
+}
+
+async fn transition(engine: &Engine, settings: &ThermalSettings, hot: bool) {
+    engine.set_degraded(HIGH_TEMPERATURE, hot).await;
+
+    let profile = if hot { settings.profile.clone() } else { None };
+
+    if let Err(err) = engine
+        .set_automatic_profile(Trigger::Thermal, profile)
+        .await
+    {
+        log::error!("Failed to switch profile on temperature change: {}", err);
+    }
+}
+
+/// Zones with a threshold, either the configured one or their own trip point
+async fn zones(sysfs: &Sysfs, settings: &ThermalSettings) -> Result<Vec<Zone>> {
+    let mut zones = Vec::new();
+
+    for name in sysfs.list(THERMAL, "thermal_zone").await? {
+        let zone = format!("{}/{}", THERMAL, name);
+
+        let threshold = match settings.above {
+            Some(above) => Some(i64::from(above) * 1000),
+            None => trip_point(sysfs, &zone, &settings.trip_point).await,
+        };
+
+        let Some(threshold) = threshold else {
+            continue;
+        };
+
+        // Some zones fail to read while their sensor is powered down
+        match sysfs
+            .read(format!("{}/temp", zone))
+            .await
+            .and_then(|temperature| Ok(temperature.parse()?))
+        {
+            Ok(temperature) => zones.push(Zone {
+                name,
+                temperature,
+                threshold,
+            }),
+            Err(err) => log::debug!("Skipping {}: {}", name, err),
+        }
+    }
+
+    Ok(zones)
+}
+
+/// The lowest trip point of a type, in millidegrees
+async fn trip_point(sysfs: &Sysfs, zone: &str, trip_type: &str) -> Option<i64> {
+    let mut lowest = None;
+
+    for trip in sysfs.list(zone, "trip_point_").await.ok()? {
+        let Some(index) = trip
+            .strip_prefix("trip_point_")
+            .and_then(|trip| trip.strip_suffix("_type"))
+        else {
+            continue;
+        };
+
+        match sysfs.read(format!("{}/{}", zone, trip)).await {
+            Ok(found) if found == trip_type => (),
+            _ => continue,
+        }
+
+        let temperature = sysfs
+            .read(format!("{}/trip_point_{}_temp", zone, index))
+            .await
+            .ok()
+            .and_then(|temperature| temperature.parse::<i64>().ok())
+            // Disabled trip points read as 0 or less
+            .filter(|temperature| *temperature > 0);
+
+        if let Some(temperature) = temperature {
+            lowest = Some(lowest.map_or(temperature, |lowest: i64| lowest.min(temperature)));
+        }
+    }
+
+    lowest
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sysfs::tests::fixture;
+
+    const ZONE: &str = "/sys/class/thermal/thermal_zone0";
+
+    fn zone(temperature: i64) -> Zone {
+        Zone {
+            name: "thermal_zone0".to_string(),
+            temperature,
+            threshold: 90000,
+        }
+    }
+
+    #[test]
+    fn hot_from_the_threshold_on() {
+        let settings = ThermalSettings::default();
+
+        assert!(!is_hot(false, &[zone(89999)], &settings));
+        assert!(is_hot(false, &[zone(90000)], &settings));
+        assert!(is_hot(false, &[zone(40000), zone(95000)], &settings));
+    }
+
+    #[test]
+    fn recovers_below_the_hysteresis_only() {
+        let settings = ThermalSettings::default();
+
+        assert!(is_hot(true, &[zone(89000)], &settings));
+        assert!(is_hot(true, &[zone(85000)], &settings));
+        assert!(!is_hot(true, &[zone(84999)], &settings));
+
+        // Every zone has to cool down
+        assert!(is_hot(true, &[zone(40000), zone(86000)], &settings));
+    }
+
+    #[async_std::test]
+    async fn trip_point_is_the_lowest_of_its_type() {
+        let (_root, sysfs) = fixture(&[
+            (
+                "sys/class/thermal/thermal_zone0/trip_point_0_type",
+                "critical\n",
+            ),
+            (
+                "sys/class/thermal/thermal_zone0/trip_point_0_temp",
+                "80000\n",
+            ),
+            (
+                "sys/class/thermal/thermal_zone0/trip_point_1_type",
+                "passive\n",
+            ),
+            (
+                "sys/class/thermal/thermal_zone0/trip_point_1_temp",
+                "95000\n",
+            ),
+            (
+                "sys/class/thermal/thermal_zone0/trip_point_2_type",
+                "passive\n",
+            ),
+            (
+                "sys/class/thermal/thermal_zone0/trip_point_2_temp",
+                "90000\n",
+            ),
+            // Disabled, and missing its temperature
+            (
+                "sys/class/thermal/thermal_zone0/trip_point_3_type",
+                "passive\n",
+            ),
+            ("sys/class/thermal/thermal_zone0/trip_point_3_temp", "0\n"),
+            (
+                "sys/class/thermal/thermal_zone0/trip_point_4_type",
+                "passive\n",
+            ),
+        ]);
+
+        assert_eq!(trip_point(&sysfs, ZONE, "passive").await, Some(90000));
+        assert_eq!(trip_point(&sysfs, ZONE, "critical").await, Some(80000));
+        assert_eq!(trip_point(&sysfs, ZONE, "hot").await, None);
+    }
+
+    #[async_std::test]
+    async fn zones_use_the_configured_threshold_over_trip_points() {
+        let (_root, sysfs) = fixture(&[
+            ("sys/class/thermal/thermal_zone0/temp", "70000\n"),
+            (
+                "sys/class/thermal/thermal_zone0/trip_point_0_type",
+                "passive\n",
+            ),
+            (
+                "sys/class/thermal/thermal_zone0/trip_point_0_temp",
+                "90000\n",
+            ),
+            // No passive trip point
+            ("sys/class/thermal/thermal_zone1/temp", "50000\n"),
+            // Unreadable while the sensor is powered down
+            ("sys/class/thermal/thermal_zone2/temp", "\n"),
+            (
+                "sys/class/thermal/thermal_zone2/trip_point_0_type",
+                "passive\n",
+            ),
+            (
+                "sys/class/thermal/thermal_zone2/trip_point_0_temp",
+                "90000\n",
+            ),
+        ]);
+
+        let found = zones(&sysfs, &ThermalSettings::default()).await.unwrap();
+
+        assert_eq!(found.len(), 1);
+        assert_eq!(found[0].name, "thermal_zone0");
+        assert_eq!(found[0].temperature, 70000);
+        assert_eq!(found[0].threshold, 90000);
+
+        let settings = ThermalSettings {
+            above: Some(60),
+            ..Default::default()
+        };
+        let mut found = zones(&sysfs, &settings).await.unwrap();
+
+        found.sort_by(|a, b| a.name.cmp(&b.name));
+
+        assert_eq!(found.len(), 2);
+        assert!(found.iter().all(|zone| zone.threshold == 60000));
+        assert_eq!(found[1].name, "thermal_zone1");
+    }
+}
@@ -0,0 +1,36 @@
+use std::path::Path;
+
+use anyhow::{Context, Result};
+use async_std::fs;
+
+/// Read the profile last selected by the user, if one was recorded
+pub(crate) async fn load(path: &str) -> Option<String> {
+    match fs::read_to_string(path).await {
+        Ok(contents) => Some(contents.trim().to_owned()).filter(|profile| !profile.is_empty()),
+        Err(err) => {
+            log::debug!("No selected profile restored from {}: {}", path, err);
+            None
+        }
+    }
+}
+
+/// Record the profile selected by the user so it survives restarts
+pub(crate) async fn store(path: &str, profile: &str) -> Result<()> {
+    if let Some(parent) = Path::new(path).parent() {
+        fs::create_dir_all(parent)
+            .await
+            .with_context(|| format!("Failed to create {}", parent.display()))?;
+    }
+
+    // Write then rename, so a crash can't leave a truncated file behind
+    let staging = format!("{}.tmp", path);
+
+    fs::write(&staging, format!("{}\n", profile))
+        .await
+        .with_context(|| format!("Failed to write {}", staging))?;
+    fs::rename(&staging, path)
+        .await
+        .with_context(|| format!("Failed to replace {}", path))?;
+
+    Ok(())
+}
@@ -1,7 +1,10 @@
-use std::collections::HashMap;
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+};
 
-use anyhow::Result;
-use config::Config;
+use anyhow::{Context, Result};
+use config::{Config, Map, Value, ValueKind};
 use serde::Deserialize;
 use serde_with::{serde_as, KeyValueMap};
 
@@ -11,13 +14,231 @@ use crate::types::{InferredPowerProfile, PowerProfile};
 pub(crate) struct Settings {
     pub(crate) default: String,
     profiles: HashMap<String, PowerProfile>,
+    pub(crate) drift: DriftSettings,
+    pub(crate) verify: VerifySettings,
+    pub(crate) power_source: PowerSourceSettings,
+    pub(crate) low_battery: LowBatterySettings,
+    pub(crate) actions: ActionSettings,
+    pub(crate) thermal: ThermalSettings,
+    pub(crate) lap: LapSettings,
+    /// Where the profile selected by the user is recorded across restarts
+    pub(crate) state_file: String,
+    /// Directory sysfs paths are resolved against, "/" on a real system
+    pub(crate) sysfs_root: String,
+}
+
+const DEFAULT_STATE_FILE: &str = "/var/lib/powerr-profiles-daemon/selected_profile";
+const DEFAULT_SYSFS_ROOT: &str = "/";
+
+/// Directory next to the config file whose fragments override it
+const DROP_IN_DIRECTORY: &str = "conf.d";
+/// Formats fragments can be written in, anything else in the directory is ignored
+const DROP_IN_EXTENSIONS: [&str; 4] = ["json", "toml", "yaml", "yml"];
+
+/// Watches for changes made to the CPU settings behind the daemon's back
+#[derive(Clone, Debug, Deserialize)]
+#[serde(default)]
+pub(crate) struct DriftSettings {
+    /// Seconds between polls of the cpufreq policy files, 0 disables the watcher
+    pub(crate) interval: u64,
+    /// Re-apply the selected profile when the hardware no longer matches it
+    pub(crate) reapply: bool,
+}
+
+impl Default for DriftSettings {
+    fn default() -> Self {
+        Self {
+            interval: 5,
+            reapply: false,
+        }
+    }
+}
+
+/// Checks that the kernel accepted every write of a profile activation
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(default)]
+pub(crate) struct VerifySettings {
+    /// Fail (and roll back) the activation instead of only warning about mismatches
+    pub(crate) strict: bool,
+}
+
+/// Profiles switched to automatically when the machine is plugged in or unplugged.
+///
+/// Holds and profiles selected by the user after the switch take priority.
+#[derive(Clone, Debug, Deserialize)]
+#[serde(default)]
+pub(crate) struct PowerSourceSettings {
+    /// Profile used on AC power, unset to go back to the one selected by the user
+    pub(crate) ac: Option<String>,
+    /// Profile used on battery power, unset to keep the one selected by the user
+    pub(crate) battery: Option<String>,
+    /// Seconds between polls of the power supplies
+    pub(crate) interval: u64,
+}
+
+impl Default for PowerSourceSettings {
+    fn default() -> Self {
+        Self {
+            ac: None,
+            battery: None,
+            interval: 5,
+        }
+    }
+}
+
+impl PowerSourceSettings {
+    pub(crate) fn enabled(&self) -> bool {
+        self.ac.is_some() || self.battery.is_some()
+    }
+}
+
+/// Switches to a profile while the battery runs low, winning over the power source rules.
+///
+/// The profile is switched to when the charge drops below `below` while discharging, and
+/// switched away from once it is back above `above`.
+#[derive(Clone, Debug, Deserialize)]
+#[serde(default)]
+pub(crate) struct LowBatterySettings {
+    /// Profile used while the battery is low, unset to disable
+    pub(crate) profile: Option<String>,
+    /// Percentage of charge the battery is low below
+    pub(crate) below: u8,
+    /// Percentage of charge the battery has to get back above
+    pub(crate) above: u8,
+    /// Seconds between polls of the batteries
+    pub(crate) interval: u64,
+}
+
+impl Default for LowBatterySettings {
+    fn default() -> Self {
+        Self {
+            profile: None,
+            below: 20,
+            above: 30,
+            interval: 30,
+        }
+    }
+}
+
+impl LowBatterySettings {
+    pub(crate) fn enabled(&self) -> bool {
+        self.profile.is_some()
+    }
+}
+
+/// Actions carried out on top of the drivers, each is only used if the hardware has it
+#[derive(Clone, Debug, Deserialize)]
+#[serde(default)]
+pub(crate) struct ActionSettings {
+    /// Charge peripherals slowly in power-saver
+    pub(crate) trickle_charge: bool,
+    /// Save power on amdgpu driven built-in panels in power-saver
+    pub(crate) amdgpu_panel_power: bool,
+}
+
+impl Default for ActionSettings {
+    fn default() -> Self {
+        Self {
+            trickle_charge: true,
+            amdgpu_panel_power: true,
+        }
+    }
+}
+
+/// Reports performance as degraded while a thermal zone runs hot
+#[derive(Clone, Debug, Deserialize)]
+#[serde(default)]
+pub(crate) struct ThermalSettings {
+    pub(crate) enabled: bool,
+    /// Degrees Celsius a zone is hot above, unset to use the zone's own trip point
+    pub(crate) above: Option<u32>,
+    /// Type of the trip point used when no temperature is configured, e.g. "passive"
+    pub(crate) trip_point: String,
+    /// Degrees Celsius every zone has to cool down below the threshold to recover
+    pub(crate) hysteresis: u32,
+    /// Profile forced until the temperatures recover, over holds and the user's choice
+    pub(crate) profile: Option<String>,
+    /// Seconds between polls of the thermal zones
+    pub(crate) interval: u64,
+}
+
+impl Default for ThermalSettings {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            above: None,
+            trip_point: "passive".to_string(),
+            hysteresis: 5,
+            profile: None,
+            interval: 5,
+        }
+    }
+}
+
+/// Reports performance as degraded while the laptop sits on the user's lap
+#[derive(Clone, Debug, Deserialize)]
+#[serde(default)]
+pub(crate) struct LapSettings {
+    pub(crate) enabled: bool,
+    /// Attributes reading 1 on a lap, the first one that exists is used.
+    ///
+    /// Only thinkpad_acpi's is listed out of the box, it is the only mainline driver known
+    /// to expose lap mode as an attribute. Dell and Intel machines have no stable upstream
+    /// one, so rather than guessing a path theirs has to be configured, the watcher stays
+    /// idle otherwise.
+    pub(crate) attributes: Vec<String>,
+    /// Refuse the performance profile on a lap, selections and holds fall back to the default
+    pub(crate) block_performance: bool,
+    /// Seconds between polls, the attributes don't raise change events
+    pub(crate) interval: u64,
+}
+
+impl Default for LapSettings {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            attributes: vec![
+                // Lenovo laptops driven by thinkpad_acpi
+                "/sys/devices/platform/thinkpad_acpi/dytc_lapmode".to_string(),
+            ],
+            block_performance: false,
+            interval: 2,
+        }
+    }
 }
 
 impl Settings {
-    fn new(default: String, profiles: HashMap<String, PowerProfile>) -> Result<Self> {
+    fn new(default: String, raw_profiles: Vec<RawPowerProfile>) -> Result<Self> {
+        let raw_profiles: HashMap<String, RawPowerProfile> = raw_profiles
+            .into_iter()
+            .map(|profile| (profile.name.clone(), profile))
+            .collect();
+
+        let mut profiles = HashMap::new();
+
+        for name in raw_profiles.keys() {
+            let fields = resolve(&raw_profiles, name, &mut Vec::new())?;
+
+            let mut profile: PowerProfile = Value::new(None, ValueKind::Table(fields))
+                .try_deserialize()
+                .with_context(|| format!("Profile {} is invalid", name))?;
+            profile.name = name.clone();
+
+            profiles.insert(name.clone(), profile);
+        }
+
         let instance = Self {
             default: default,
             profiles: profiles,
+            drift: DriftSettings::default(),
+            verify: VerifySettings::default(),
+            power_source: PowerSourceSettings::default(),
+            low_battery: LowBatterySettings::default(),
+            actions: ActionSettings::default(),
+            thermal: ThermalSettings::default(),
+            lap: LapSettings::default(),
+            state_file: DEFAULT_STATE_FILE.to_string(),
+            sysfs_root: DEFAULT_SYSFS_ROOT.to_string(),
         };
 
         match instance.profiles.get(&instance.default) {
@@ -30,56 +251,696 @@ impl Settings {
     }
 }
 
+/// The fields of a profile, with the ones it inherits filled in.
+///
+/// `chain` holds the profiles inheriting from this one, to detect cycles.
+fn resolve(
+    raw_profiles: &HashMap<String, RawPowerProfile>,
+    name: &String,
+    chain: &mut Vec<String>,
+) -> Result<Map<String, Value>> {
+    if chain.contains(name) {
+        chain.push(name.clone());
+
+        return Err(anyhow::anyhow!(
+            "Profile {} inherits from itself: {}",
+            name,
+            chain.join(" -> ")
+        ));
+    }
+
+    let profile = match (raw_profiles.get(name), chain.last()) {
+        (Some(profile), _) => profile,
+        (None, Some(child)) => {
+            return Err(anyhow::anyhow!(
+                "Profile {} inherits from {}, which is not configured!",
+                child,
+                name
+            ))
+        }
+        (None, None) => return Err(anyhow::anyhow!("Profile {} is not configured!", name)),
+    };
+
+    let Some(parent) = &profile.inherits else {
+        return Ok(profile.fields.clone());
+    };
+
+    chain.push(name.clone());
+    let inherited = resolve(raw_profiles, parent, chain)?;
+    chain.pop();
+
+    Ok(merge(inherited, profile.fields.clone()))
+}
+
+/// Tables are merged key by key, anything else in `overrides` replaces what is in `base`
+fn merge(mut base: Map<String, Value>, overrides: Map<String, Value>) -> Map<String, Value> {
+    for (key, value) in overrides {
+        let merged = match (base.remove(&key), value.kind) {
+            (
+                Some(Value {
+                    kind: ValueKind::Table(base),
+                    ..
+                }),
+                ValueKind::Table(overrides),
+            ) => Value::new(None, ValueKind::Table(merge(base, overrides))),
+            (_, kind) => Value::new(None, kind),
+        };
+
+        base.insert(key, merged);
+    }
+
+    base
+}
+
+/// A profile as written in the config file, before inheritance is resolved
+#[derive(Clone, Debug, Deserialize)]
+struct RawPowerProfile {
+    #[serde(rename = "$key$")]
+    name: String,
+    /// Profile providing every field this one leaves out
+    inherits: Option<String>,
+    #[serde(flatten)]
+    fields: Map<String, Value>,
+}
+
 #[serde_as]
 #[derive(Clone, Debug, Deserialize)]
 struct RawSettings {
     default: String,
     #[serde_as(as = "KeyValueMap<_>")]
-    profiles: Vec<PowerProfile>,
+    profiles: Vec<RawPowerProfile>,
+    #[serde(default)]
+    drift: DriftSettings,
+    #[serde(default)]
+    verify: VerifySettings,
+    #[serde(default)]
+    power_source: PowerSourceSettings,
+    #[serde(default)]
+    low_battery: LowBatterySettings,
+    #[serde(default)]
+    actions: ActionSettings,
+    #[serde(default)]
+    thermal: ThermalSettings,
+    #[serde(default)]
+    lap: LapSettings,
+    state_file: Option<String>,
+    sysfs_root: Option<String>,
 }
 
 impl TryInto<Settings> for RawSettings {
     type Error = anyhow::Error;
 
     fn try_into(self) -> Result<Settings> {
-        Settings::new(
-            self.default,
-            self.profiles
-                .into_iter()
-                .map(|profile| (profile.name.clone(), profile.into()))
-                .collect(),
-        )
+        let settings = Settings {
+            drift: self.drift,
+            verify: self.verify,
+            power_source: self.power_source,
+            low_battery: self.low_battery,
+            actions: self.actions,
+            thermal: self.thermal,
+            lap: self.lap,
+            state_file: self
+                .state_file
+                .unwrap_or_else(|| DEFAULT_STATE_FILE.to_string()),
+            sysfs_root: self
+                .sysfs_root
+                .unwrap_or_else(|| DEFAULT_SYSFS_ROOT.to_string()),
+            ..Settings::new(self.default, self.profiles)?
+        };
+
+        for profile in [&settings.power_source.ac, &settings.power_source.battery]
+            .into_iter()
+            .flatten()
+        {
+            if settings.profile_by_name(profile).is_none() {
+                return Err(anyhow::anyhow!(
+                    "Power source profile {} is not configured!",
+                    profile
+                ));
+            }
+        }
+
+        if let Some(profile) = &settings.thermal.profile {
+            if settings.profile_by_name(profile).is_none() {
+                return Err(anyhow::anyhow!(
+                    "Thermal profile {} is not configured!",
+                    profile
+                ));
+            }
+        }
+
+        // Blocked profiles fall back to the default one
+        if settings.lap.block_performance && settings.default == crate::holds::PERFORMANCE {
+            return Err(anyhow::anyhow!(
+                "The default profile can't be blocked on a lap!"
+            ));
+        }
+
+        if let Some(profile) = &settings.low_battery.profile {
+            if settings.profile_by_name(profile).is_none() {
+                return Err(anyhow::anyhow!(
+                    "Low battery profile {} is not configured!",
+                    profile
+                ));
+            }
+
+            // Without a gap the profile would flap around a single percentage
+            if settings.low_battery.below >= settings.low_battery.above {
+                return Err(anyhow::anyhow!(
+                    "Low battery threshold {} has to be below {}!",
+                    settings.low_battery.below,
+                    settings.low_battery.above
+                ));
+            }
+
+            // The charge never gets back above more than 100%
+            if settings.low_battery.above > 100 {
+                return Err(anyhow::anyhow!(
+                    "Low battery recovery threshold {} is above 100%!",
+                    settings.low_battery.above
+                ));
+            }
+        }
+
+        Ok(settings)
     }
 }
 
 impl Settings {
+    /// Load the config file, the fragments of its drop-in directory and `PPD_` variables.
+    ///
+    /// Fragments are merged in lexical order, each only needs the fields it changes.
     pub fn build(config_path: &str) -> Result<Self> {
-        Config::builder()
-            .add_source(config::File::with_name(config_path))
+        let mut builder = Config::builder().add_source(config::File::with_name(config_path));
+
+        for fragment in Self::drop_ins(config_path)? {
+            log::debug!("Merging settings from {}", fragment.display());
+
+            builder = builder.add_source(config::File::from(fragment));
+        }
+
+        builder
             .add_source(config::Environment::with_prefix("PPD"))
             .build()?
             .try_deserialize::<RawSettings>()?
             .try_into()
     }
 
+    /// Fragments in the drop-in directory next to the config file, in the order to merge them
+    fn drop_ins(config_path: &str) -> Result<Vec<PathBuf>> {
+        let directory = Path::new(config_path)
+            .parent()
+            .unwrap_or(Path::new(""))
+            .join(DROP_IN_DIRECTORY);
+
+        if !directory.is_dir() {
+            return Ok(Vec::new());
+        }
+
+        let mut fragments = Vec::new();
+
+        for entry in std::fs::read_dir(&directory)? {
+            let path = entry?.path();
+
+            let supported = path
+                .extension()
+                .and_then(|extension| extension.to_str())
+                .is_some_and(|extension| DROP_IN_EXTENSIONS.contains(&extension));
+
+            match supported && path.is_file() {
+                true => fragments.push(path),
+                false => log::debug!("Ignoring {} in {}", path.display(), directory.display()),
+            }
+        }
+
+        fragments.sort();
+
+        Ok(fragments)
+    }
+
     pub fn profiles(&self) -> &HashMap<String, PowerProfile> {
         &self.profiles
     }
 
+    /// Profiles from the most power saving to the most performant, like upstream reports them.
+    ///
+    /// Profiles upstream doesn't know about come last, by name.
+    pub fn ordered_profiles(&self) -> Vec<&PowerProfile> {
+        let rank = |name: &str| match name {
+            crate::holds::POWER_SAVER => 0,
+            "balanced" => 1,
+            crate::holds::PERFORMANCE => 2,
+            _ => 3,
+        };
+
+        let mut profiles: Vec<&PowerProfile> = self.profiles.values().collect();
+        profiles.sort_by(|a, b| (rank(&a.name), &a.name).cmp(&(rank(&b.name), &b.name)));
+
+        profiles
+    }
+
     pub fn profile_by_name(&self, profile_name: &String) -> Option<&PowerProfile> {
         self.profiles.get(profile_name)
     }
 
+    /// The profile matching the hardware state, `preferred` if it does.
+    ///
+    /// Profiles may only differ in attributes the hardware lacks, then the first of them in
+    /// `ordered_profiles` order wins so the answer is the same on every run.
     pub fn profile_by_inferred(
         &self,
         inferred_profile: InferredPowerProfile,
+        preferred: &str,
     ) -> Option<PowerProfile> {
-        for profile in self.profiles.values().into_iter() {
+        // Nothing is known, so everything would match
+        if inferred_profile.is_empty() {
+            return None;
+        }
+
+        if let Some(profile) = self.profiles.get(preferred) {
             if *profile == inferred_profile {
                 return Some(profile.clone());
             }
         }
 
-        None
+        self.ordered_profiles()
+            .into_iter()
+            .find(|profile| **profile == inferred_profile)
+            .cloned()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use config::FileFormat;
+
+    use super::*;
+    use crate::drivers::cpu::types::{EnergyPreference, ScalingGovernor};
+
+    fn settings(profiles: &str) -> Result<Settings> {
+        let json = format!(r#"{{ "default": "balanced", "profiles": {} }}"#, profiles);
+
+        Config::builder()
+            .add_source(config::File::from_str(&json, FileFormat::Json))
+            .build()?
+            .try_deserialize::<RawSettings>()?
+            .try_into()
+    }
+
+    fn profile<'a>(settings: &'a Settings, name: &str) -> &'a PowerProfile {
+        settings.profile_by_name(&name.to_string()).unwrap()
+    }
+
+    #[test]
+    fn child_overrides_one_cpu_field() {
+        let settings = settings(
+            r#"{
+                "balanced": {
+                    "cpu": {
+                        "boost": true,
+                        "energy_preference": "balancePower",
+                        "scaling_governor": "powersave",
+                        "maximum_frequency": 3000000
+                    },
+                    "platform": { "profile": "balanced" }
+                },
+                "quiet": {
+                    "inherits": "balanced",
+                    "cpu": { "boost": false }
+                }
+            }"#,
+        )
+        .unwrap();
+
+        let quiet = profile(&settings, "quiet");
+
+        assert_eq!(quiet.name, "quiet");
+        assert!(!quiet.cpu.boost);
+        assert_eq!(quiet.cpu.energy_preference, EnergyPreference::BalancePower);
+        assert_eq!(quiet.cpu.scaling_governor, ScalingGovernor::Powersave);
+        assert_eq!(quiet.cpu.maximum_frequency, Some(3000000));
+        assert_eq!(quiet.platform.as_ref().unwrap().profile, "balanced");
+
+        // The parent is left as it is
+        assert!(profile(&settings, "balanced").cpu.boost);
+    }
+
+    #[test]
+    fn child_differing_in_one_field_is_told_apart() {
+        let settings = settings(
+            r#"{
+                "balanced": {
+                    "cpu": {
+                        "boost": true,
+                        "energy_preference": "balancePower",
+                        "scaling_governor": "powersave"
+                    }
+                },
+                "quiet": {
+                    "inherits": "balanced",
+                    "cpu": { "maximum_frequency": 1500000 }
+                }
+            }"#,
+        )
+        .unwrap();
+
+        let inferred = |maximum_frequency| InferredPowerProfile {
+            boost: Some(true),
+            energy_preference: Some(EnergyPreference::BalancePower),
+            scaling_governor: Some(ScalingGovernor::Powersave),
+            maximum_frequency: Some(maximum_frequency),
+            frequency_range: Some((400000, 4000000)),
+            ..Default::default()
+        };
+
+        for preferred in ["balanced", "quiet"] {
+            let quiet = settings.profile_by_inferred(inferred(1500000), preferred);
+            assert_eq!(quiet.unwrap().name, "quiet");
+
+            let balanced = settings.profile_by_inferred(inferred(4000000), preferred);
+            assert_eq!(balanced.unwrap().name, "balanced");
+        }
+    }
+
+    #[test]
+    fn inheritance_chains_resolve() {
+        let settings = settings(
+            r#"{
+                "balanced": {
+                    "cpu": {
+                        "boost": true,
+                        "energy_preference": "balancePower",
+                        "scaling_governor": "powersave"
+                    }
+                },
+                "quiet": {
+                    "inherits": "balanced",
+                    "cpu": { "boost": false }
+                },
+                "quieter": {
+                    "inherits": "quiet",
+                    "cpu": { "energy_preference": "power" }
+                }
+            }"#,
+        )
+        .unwrap();
+
+        let quieter = profile(&settings, "quieter");
+
+        assert!(!quieter.cpu.boost);
+        assert_eq!(quieter.cpu.energy_preference, EnergyPreference::Power);
+        assert_eq!(quieter.cpu.scaling_governor, ScalingGovernor::Powersave);
+    }
+
+    #[test]
+    fn inheritance_cycle() {
+        let err = settings(
+            r#"{
+                "balanced": {
+                    "cpu": {
+                        "boost": true,
+                        "energy_preference": "balancePower",
+                        "scaling_governor": "powersave"
+                    }
+                },
+                "a": { "inherits": "b" },
+                "b": { "inherits": "a" }
+            }"#,
+        )
+        .unwrap_err()
+        .to_string();
+
+        assert!(
+            err == "Profile a inherits from itself: a -> b -> a"
+                || err == "Profile b inherits from itself: b -> a -> b",
+            "{}",
+            err
+        );
+    }
+
+    #[test]
+    fn missing_parent() {
+        let err = settings(
+            r#"{
+                "balanced": {
+                    "inherits": "missing",
+                    "cpu": {
+                        "boost": true,
+                        "energy_preference": "balancePower",
+                        "scaling_governor": "powersave"
+                    }
+                }
+            }"#,
+        )
+        .unwrap_err()
+        .to_string();
+
+        assert_eq!(
+            err,
+            "Profile balanced inherits from missing, which is not configured!"
+        );
+    }
+
+    /// Profiles that only differ in their maximum frequency, like on acpi-cpufreq
+    fn frequency_profiles() -> Settings {
+        settings(
+            r#"{
+                "balanced": {
+                    "cpu": {
+                        "boost": true,
+                        "energy_preference": "balancePower",
+                        "scaling_governor": "powersave"
+                    }
+                },
+                "fast": {
+                    "cpu": {
+                        "boost": true,
+                        "energy_preference": "balancePower",
+                        "scaling_governor": "powersave",
+                        "maximum_frequency": 5000000
+                    }
+                },
+                "quiet": {
+                    "cpu": {
+                        "boost": true,
+                        "energy_preference": "balancePower",
+                        "scaling_governor": "powersave",
+                        "maximum_frequency": 1500000
+                    }
+                }
+            }"#,
+        )
+        .unwrap()
+    }
+
+    fn inferred(maximum_frequency: u32) -> InferredPowerProfile {
+        InferredPowerProfile {
+            boost: Some(true),
+            scaling_governor: Some(ScalingGovernor::Powersave),
+            maximum_frequency: Some(maximum_frequency),
+            frequency_range: Some((800000, 3000000)),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn inferred_by_maximum_frequency() {
+        let settings = frequency_profiles();
+        let name = |maximum_frequency| {
+            settings
+                .profile_by_inferred(inferred(maximum_frequency), "balanced")
+                .map(|profile| profile.name)
+        };
+
+        assert_eq!(name(1500000).as_deref(), Some("quiet"));
+        assert_eq!(name(3000000).as_deref(), Some("balanced"));
+        assert_eq!(name(2000000), None);
+    }
+
+    #[test]
+    fn inferred_prefers_the_effective_profile() {
+        let settings = frequency_profiles();
+
+        // Both end up at cpuinfo_max_freq, 5000000 is clamped to it
+        let fast = settings.profile_by_inferred(inferred(3000000), "fast");
+        assert_eq!(fast.unwrap().name, "fast");
+
+        let balanced = settings.profile_by_inferred(inferred(3000000), "quiet");
+        assert_eq!(balanced.unwrap().name, "balanced");
+    }
+
+    #[test]
+    fn inferred_without_frequency_range() {
+        let settings = frequency_profiles();
+        let inferred = InferredPowerProfile {
+            frequency_range: None,
+            ..inferred(1500000)
+        };
+
+        // Only profiles configuring another maximum can be ruled out
+        let profile = settings.profile_by_inferred(inferred, "quiet");
+        assert_eq!(profile.unwrap().name, "quiet");
+    }
+
+    #[test]
+    fn merge_replaces_everything_but_tables() {
+        let table = |entries: &[(&str, Value)]| -> Map<String, Value> {
+            entries
+                .iter()
+                .map(|(key, value)| (key.to_string(), value.clone()))
+                .collect()
+        };
+
+        let base = table(&[
+            ("boost", Value::from(true)),
+            (
+                "driver_options",
+                Value::from(table(&[("a", Value::from(1)), ("b", Value::from(2))])),
+            ),
+        ]);
+        let overrides = table(&[
+            ("boost", Value::from(false)),
+            (
+                "driver_options",
+                Value::from(table(&[("b", Value::from(3))])),
+            ),
+        ]);
+
+        let merged = merge(base, overrides);
+
+        assert!(!merged["boost"].clone().into_bool().unwrap());
+
+        let options = merged["driver_options"].clone().into_table().unwrap();
+        assert_eq!(options["a"].clone().into_int().unwrap(), 1);
+        assert_eq!(options["b"].clone().into_int().unwrap(), 3);
+    }
+
+    fn low_battery(below: u8, above: u8) -> Result<Settings> {
+        let json = serde_json::json!({
+            "default": "balanced",
+            "low_battery": { "profile": "balanced", "below": below, "above": above },
+            "profiles": {
+                "balanced": {
+                    "cpu": {
+                        "boost": true,
+                        "energy_preference": "balancePower",
+                        "scaling_governor": "powersave"
+                    }
+                }
+            }
+        });
+
+        Config::builder()
+            .add_source(config::File::from_str(&json.to_string(), FileFormat::Json))
+            .build()?
+            .try_deserialize::<RawSettings>()?
+            .try_into()
+    }
+
+    #[test]
+    fn low_battery_thresholds() {
+        assert!(low_battery(20, 30).is_ok());
+        assert!(low_battery(0, 100).is_ok());
+
+        let err = low_battery(30, 30).unwrap_err().to_string();
+        assert!(err.contains("has to be below"), "{}", err);
+
+        let err = low_battery(40, 30).unwrap_err().to_string();
+        assert!(err.contains("has to be below"), "{}", err);
+
+        let err = low_battery(20, 101).unwrap_err().to_string();
+        assert!(err.contains("above 100%"), "{}", err);
+    }
+
+    /// A config file with a balanced profile, and a drop-in directory holding `fragments`
+    fn drop_in_config(fragments: &[(&str, &str)]) -> (tempfile::TempDir, String) {
+        let root = tempfile::tempdir().unwrap();
+        let config = root.path().join("config.json");
+
+        std::fs::write(
+            &config,
+            r#"{
+                "default": "balanced",
+                "drift": { "interval": 5 },
+                "profiles": {
+                    "balanced": {
+                        "cpu": {
+                            "boost": true,
+                            "energy_preference": "balancePower",
+                            "scaling_governor": "powersave",
+                            "maximum_frequency": 3000000
+                        },
+                        "platform": { "profile": "balanced" }
+                    }
+                }
+            }"#,
+        )
+        .unwrap();
+
+        std::fs::create_dir(root.path().join(DROP_IN_DIRECTORY)).unwrap();
+
+        for (name, content) in fragments {
+            std::fs::write(root.path().join(DROP_IN_DIRECTORY).join(name), content).unwrap();
+        }
+
+        let config = config.to_string_lossy().into_owned();
+
+        (root, config)
+    }
+
+    #[test]
+    fn drop_ins_are_sorted_lexically() {
+        let (root, config) = drop_in_config(&[
+            ("20-b.toml", ""),
+            ("10-a.json", "{}"),
+            ("30-c.yaml", ""),
+            ("05-notes.md", ""),
+            ("25-backup.json~", ""),
+        ]);
+
+        std::fs::create_dir(root.path().join(DROP_IN_DIRECTORY).join("15-dir.json")).unwrap();
+
+        let names: Vec<String> = Settings::drop_ins(&config)
+            .unwrap()
+            .iter()
+            .map(|path| path.file_name().unwrap().to_string_lossy().into_owned())
+            .collect();
+
+        assert_eq!(names, ["10-a.json", "20-b.toml", "30-c.yaml"]);
+    }
+
+    #[test]
+    fn drop_ins_of_every_format_merge_in_order() {
+        let (_root, config) = drop_in_config(&[
+            ("30-drift.yaml", "drift:\n  reapply: true\n"),
+            ("20-drift.toml", "[drift]\ninterval = 20\n"),
+            ("10-drift.json", r#"{ "drift": { "interval": 10 } }"#),
+        ]);
+
+        let settings = Settings::build(&config).unwrap();
+
+        // The TOML fragment sorts after the JSON one, the YAML one leaves the interval alone
+        assert_eq!(settings.drift.interval, 20);
+        assert!(settings.drift.reapply);
+    }
+
+    #[test]
+    fn drop_in_overrides_a_single_profile_field() {
+        let (_root, config) = drop_in_config(&[(
+            "10-no-boost.toml",
+            "[profiles.balanced.cpu]\nboost = false\n",
+        )]);
+
+        let settings = Settings::build(&config).unwrap();
+        let balanced = profile(&settings, "balanced");
+
+        assert!(!balanced.cpu.boost);
+        assert_eq!(
+            balanced.cpu.energy_preference,
+            EnergyPreference::BalancePower
+        );
+        assert_eq!(balanced.cpu.scaling_governor, ScalingGovernor::Powersave);
+        assert_eq!(balanced.cpu.maximum_frequency, Some(3000000));
+        assert_eq!(balanced.platform.as_ref().unwrap().profile, "balanced");
     }
 }
@@ -0,0 +1,360 @@
+use std::{
+    collections::BTreeMap,
+    fmt,
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+};
+
+use anyhow::{Context, Result};
+use async_std::{fs, stream::StreamExt};
+
+/// Access to sysfs (and other kernel files) relative to a configurable root.
+///
+/// Paths are always written as on a real system, e.g. `/sys/devices/system/cpu/online`, and
+/// resolved below the root so the daemon can run against a fixture directory.
+///
+/// Every write is recorded in a plan, in dry-run mode the write itself is skipped.
+#[derive(Clone, Debug)]
+pub(crate) struct Sysfs {
+    root: PathBuf,
+    dry_run: bool,
+    plan: Arc<Mutex<Vec<Write>>>,
+}
+
+/// A single attribute write, as it happened or would have happened
+#[derive(Clone, Debug)]
+pub(crate) struct Write {
+    pub path: PathBuf,
+    /// None if the attribute could not be read beforehand
+    pub old: Option<String>,
+    pub new: String,
+    /// Why the write failed, if it did
+    pub error: Option<String>,
+}
+
+/// An attribute that doesn't hold the value last written to it
+#[derive(Clone, Debug)]
+pub(crate) struct Mismatch {
+    pub path: PathBuf,
+    pub expected: String,
+    pub actual: String,
+}
+
+/// A failed activation whose writes were undone
+#[derive(Debug)]
+pub(crate) struct Rollback {
+    /// Writes that failed, with the reason
+    pub failed: Vec<(PathBuf, String)>,
+    /// Attributes restored to their previous value
+    pub reverted: Vec<PathBuf>,
+    /// Attributes left with the new value, with the reason
+    pub unreverted: Vec<(PathBuf, String)>,
+}
+
+impl fmt::Display for Rollback {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let failed: Vec<String> = self
+            .failed
+            .iter()
+            .map(|(path, err)| format!("{} ({})", path.display(), err))
+            .collect();
+        let reverted: Vec<String> = self
+            .reverted
+            .iter()
+            .map(|path| path.display().to_string())
+            .collect();
+        let unreverted: Vec<String> = self
+            .unreverted
+            .iter()
+            .map(|(path, err)| format!("{} ({})", path.display(), err))
+            .collect();
+
+        write!(
+            f,
+            "Profile activation rolled back (failed: [{}], reverted: [{}], not reverted: [{}])",
+            failed.join(", "),
+            reverted.join(", "),
+            unreverted.join(", ")
+        )
+    }
+}
+
+impl std::error::Error for Rollback {}
+
+impl Default for Sysfs {
+    fn default() -> Self {
+        Self::new("/", false)
+    }
+}
+
+impl Sysfs {
+    pub fn new(root: impl Into<PathBuf>, dry_run: bool) -> Self {
+        Self {
+            root: root.into(),
+            dry_run,
+            plan: Arc::new(Mutex::new(Vec::new())),
+        }
+    }
+
+    pub fn dry_run(&self) -> bool {
+        self.dry_run
+    }
+
+    /// The writes recorded since the last call
+    pub fn take_plan(&self) -> Vec<Write> {
+        std::mem::take(&mut self.plan.lock().unwrap())
+    }
+
+    pub fn path(&self, path: impl AsRef<Path>) -> PathBuf {
+        let path = path.as_ref();
+
+        self.root.join(path.strip_prefix("/").unwrap_or(path))
+    }
+
+    /// Names of the entries of a directory starting with `prefix`, in no particular order
+    pub async fn list(&self, path: impl AsRef<Path>, prefix: &str) -> Result<Vec<String>> {
+        let path = self.path(path);
+        let mut entries = fs::read_dir(&path)
+            .await
+            .with_context(|| format!("Failed to list {}", path.display()))?;
+        let mut names = Vec::new();
+
+        while let Some(entry) = entries.next().await {
+            let name = entry?.file_name().to_string_lossy().into_owned();
+
+            if name.starts_with(prefix) {
+                names.push(name);
+            }
+        }
+
+        Ok(names)
+    }
+
+    pub async fn exists(&self, path: impl AsRef<Path>) -> bool {
+        fs::metadata(self.path(path)).await.is_ok()
+    }
+
+    /// Read an attribute, without the trailing newline
+    pub async fn read(&self, path: impl AsRef<Path>) -> Result<String> {
+        let path = self.path(path);
+
+        Ok(fs::read_to_string(&path)
+            .await
+            .with_context(|| format!("Failed to read from {}", path.display()))?
+            .trim()
+            .to_owned())
+    }
+
+    pub async fn write(&self, path: impl AsRef<Path>, value: impl AsRef<str>) -> Result<()> {
+        let path = self.path(path);
+        // Snapshot the previous value so the write can be rolled back
+        let old = fs::read_to_string(&path)
+            .await
+            .ok()
+            .map(|old| old.trim().to_owned());
+
+        let result = match self.dry_run {
+            true => {
+                log::trace!("Would write {} to {}", value.as_ref(), path.display());
+                Ok(())
+            }
+            false => Self::write_raw(&path, value.as_ref()).await,
+        };
+
+        self.plan.lock().unwrap().push(Write {
+            path,
+            old,
+            new: value.as_ref().to_owned(),
+            error: result.as_ref().err().map(|err| format!("{:#}", err)),
+        });
+
+        result
+    }
+
+    /// Read back every successfully written attribute, the kernel may clamp or ignore writes
+    pub async fn verify(&self, writes: &[Write]) -> Vec<Mismatch> {
+        if self.dry_run {
+            return Vec::new();
+        }
+
+        // Only the last write to an attribute is expected to stick
+        let expected: BTreeMap<&Path, &str> = writes
+            .iter()
+            .filter(|write| write.error.is_none())
+            .map(|write| (write.path.as_path(), write.new.as_str()))
+            .collect();
+
+        let mut mismatches = Vec::new();
+
+        for (path, expected) in expected {
+            let actual = match fs::read_to_string(path).await {
+                Ok(actual) => actual.trim().to_owned(),
+                Err(err) => format!("unreadable: {}", err),
+            };
+
+            if actual != expected {
+                mismatches.push(Mismatch {
+                    path: path.to_owned(),
+                    expected: expected.to_owned(),
+                    actual,
+                });
+            }
+        }
+
+        mismatches
+    }
+
+    /// Restore the previous value of every successful write, newest first
+    pub async fn rollback(&self, writes: &[Write]) -> Rollback {
+        let mut rollback = Rollback {
+            failed: Vec::new(),
+            reverted: Vec::new(),
+            unreverted: Vec::new(),
+        };
+
+        for write in writes.iter().rev() {
+            if let Some(err) = &write.error {
+                rollback.failed.push((write.path.clone(), err.clone()));
+                continue;
+            }
+
+            let result = match &write.old {
+                _ if self.dry_run => Ok(()),
+                Some(old) if *old == write.new => Ok(()),
+                Some(old) => Self::write_raw(&write.path, old).await,
+                None => Err(anyhow::anyhow!("previous value unknown")),
+            };
+
+            match result {
+                Ok(()) => rollback.reverted.push(write.path.clone()),
+                Err(err) => rollback
+                    .unreverted
+                    .push((write.path.clone(), format!("{:#}", err))),
+            }
+        }
+
+        rollback
+    }
+
+    async fn write_raw(path: &Path, value: &str) -> Result<()> {
+        log::trace!("Writing {} to {}", value, path.display());
+
+        fs::write(path, value)
+            .await
+            .with_context(|| format!("Failed to write to {}", path.display()))
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+
+    /// A root holding the given attributes, removed once the directory is dropped
+    pub(crate) fn fixture(attributes: &[(&str, &str)]) -> (tempfile::TempDir, Sysfs) {
+        let root = tempfile::tempdir().unwrap();
+
+        for (path, value) in attributes {
+            let path = root.path().join(path);
+
+            std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+            std::fs::write(path, value).unwrap();
+        }
+
+        let sysfs = Sysfs::new(root.path(), false);
+
+        (root, sysfs)
+    }
+
+    #[async_std::test]
+    async fn verify_reports_clamped_writes() {
+        let (root, sysfs) = fixture(&[("sys/a", "1\n"), ("sys/b", "1\n")]);
+
+        sysfs.write("/sys/a", "2").await.unwrap();
+        sysfs.write("/sys/a", "5").await.unwrap();
+        sysfs.write("/sys/b", "2").await.unwrap();
+        assert!(sysfs.write("/sys/missing/c", "2").await.is_err());
+
+        // The kernel clamps the last write to a, earlier writes and failed ones don't count
+        std::fs::write(root.path().join("sys/a"), "4\n").unwrap();
+
+        let mismatches = sysfs.verify(&sysfs.take_plan()).await;
+
+        assert_eq!(mismatches.len(), 1);
+        assert_eq!(mismatches[0].path, sysfs.path("/sys/a"));
+        assert_eq!(mismatches[0].expected, "5");
+        assert_eq!(mismatches[0].actual, "4");
+    }
+
+    #[async_std::test]
+    async fn rollback_restores_newest_first() {
+        let (_root, sysfs) = fixture(&[("sys/a", "1\n"), ("sys/b", "x\n")]);
+
+        sysfs.write("/sys/a", "2").await.unwrap();
+        sysfs.write("/sys/b", "y").await.unwrap();
+        sysfs.write("/sys/a", "3").await.unwrap();
+
+        let plan = sysfs.take_plan();
+        let rollback = sysfs.rollback(&plan).await;
+
+        // Restoring oldest first would leave the intermediate value behind
+        assert_eq!(sysfs.read("/sys/a").await.unwrap(), "1");
+        assert_eq!(sysfs.read("/sys/b").await.unwrap(), "x");
+        assert_eq!(
+            rollback.reverted,
+            vec![
+                sysfs.path("/sys/a"),
+                sysfs.path("/sys/b"),
+                sysfs.path("/sys/a")
+            ]
+        );
+        assert!(rollback.failed.is_empty());
+        assert!(rollback.unreverted.is_empty());
+    }
+
+    #[async_std::test]
+    async fn rollback_leaves_writes_with_unknown_old_value() {
+        let (_root, sysfs) = fixture(&[("sys/a", "1\n")]);
+
+        sysfs.write("/sys/new", "2").await.unwrap();
+        sysfs.write("/sys/a", "2").await.unwrap();
+
+        let plan = sysfs.take_plan();
+        assert_eq!(plan[0].old, None);
+
+        let rollback = sysfs.rollback(&plan).await;
+
+        assert_eq!(sysfs.read("/sys/new").await.unwrap(), "2");
+        assert_eq!(sysfs.read("/sys/a").await.unwrap(), "1");
+        assert_eq!(rollback.reverted, vec![sysfs.path("/sys/a")]);
+        assert_eq!(
+            rollback.unreverted,
+            vec![(sysfs.path("/sys/new"), "previous value unknown".to_string())]
+        );
+    }
+
+    #[async_std::test]
+    async fn rollback_reports_failed_writes() {
+        let (_root, sysfs) = fixture(&[("sys/a", "1\n")]);
+
+        sysfs.write("/sys/a", "2").await.unwrap();
+        assert!(sysfs.write("/sys/missing/b", "2").await.is_err());
+
+        let plan = sysfs.take_plan();
+        let rollback = sysfs.rollback(&plan).await;
+
+        assert_eq!(sysfs.read("/sys/a").await.unwrap(), "1");
+        assert_eq!(rollback.failed.len(), 1);
+        assert_eq!(rollback.failed[0].0, sysfs.path("/sys/missing/b"));
+        assert_eq!(rollback.reverted, vec![sysfs.path("/sys/a")]);
+        assert!(rollback.unreverted.is_empty());
+
+        let report = rollback.to_string();
+
+        assert!(report.contains(&format!(
+            "failed: [{} (Failed to write to",
+            sysfs.path("/sys/missing/b").display()
+        )));
+        assert!(report.contains(&format!("reverted: [{}]", sysfs.path("/sys/a").display())));
+        assert!(report.ends_with("not reverted: [])"));
+    }
+}
@@ -1,41 +1,96 @@
+use std::collections::BTreeMap;
+
 use serde::{Deserialize, Serialize};
 use zvariant::Type;
 
-#[derive(PartialEq)]
+/// Current hardware state as reported by the drivers, `None` where no driver knows
+#[derive(Default, PartialEq)]
 pub(crate) struct InferredPowerProfile {
-    pub(crate) boost: bool,
-    pub(crate) energy_preference: super::drivers::cpu::types::EnergyPreference,
-    pub(crate) scaling_governor: super::drivers::cpu::types::ScalingGovernor,
-    pub(crate) maximum_frequency: u32,
+    pub(crate) boost: Option<bool>,
+    pub(crate) energy_preference: Option<super::drivers::cpu::types::EnergyPreference>,
+    pub(crate) scaling_governor: Option<super::drivers::cpu::types::ScalingGovernor>,
+    pub(crate) maximum_frequency: Option<u32>,
+    /// The (cpuinfo_min_freq, cpuinfo_max_freq) every configured maximum is clamped to
+    pub(crate) frequency_range: Option<(u32, u32)>,
+    pub(crate) platform_profile: Option<String>,
+}
+
+impl InferredPowerProfile {
+    /// Combine with what another driver reported, the first known value wins
+    pub(crate) fn merge(self, other: Self) -> Self {
+        Self {
+            boost: self.boost.or(other.boost),
+            energy_preference: self.energy_preference.or(other.energy_preference),
+            scaling_governor: self.scaling_governor.or(other.scaling_governor),
+            maximum_frequency: self.maximum_frequency.or(other.maximum_frequency),
+            frequency_range: self.frequency_range.or(other.frequency_range),
+            platform_profile: self.platform_profile.or(other.platform_profile),
+        }
+    }
+
+    pub(crate) fn is_empty(&self) -> bool {
+        *self == Self::default()
+    }
 }
 
 #[derive(Clone, Debug, Deserialize)]
 pub(crate) struct PowerProfile {
     pub(crate) cpu: crate::drivers::cpu::types::PowerProfile,
-    #[serde(rename = "$key$")]
+    pub(crate) platform: Option<crate::drivers::platform::types::PowerProfile>,
+    /// Filled in from the key of the profile in the config file
+    #[serde(skip)]
     pub(crate) name: String,
 }
 
 impl PartialEq<InferredPowerProfile> for PowerProfile {
     fn eq(&self, other: &InferredPowerProfile) -> bool {
-        if self.cpu.boost != other.boost {
+        if other.boost.is_some_and(|boost| self.cpu.boost != boost) {
             return false;
         }
 
-        if self.cpu.energy_preference != other.energy_preference {
+        if other
+            .energy_preference
+            .is_some_and(|energy_preference| self.cpu.energy_preference != energy_preference)
+        {
             return false;
         }
-        if self.cpu.scaling_governor != other.scaling_governor {
+        if other
+            .scaling_governor
+            .is_some_and(|scaling_governor| self.cpu.scaling_governor != scaling_governor)
+        {
             return false;
         }
 
+        if let Some(maximum_frequency) = other.maximum_frequency {
+            // What activation ends up with, no maximum means the hardware's own
+            let expected = match (other.frequency_range, self.cpu.maximum_frequency) {
+                (Some((min, max)), configured) => Some(configured.unwrap_or(max).clamp(min, max)),
+                (None, configured) => configured,
+            };
+
+            if expected.is_some_and(|expected| expected != maximum_frequency) {
+                return false;
+            }
+        }
+
+        // Profiles that don't configure the platform match any platform profile
+        if let (Some(platform), Some(platform_profile)) = (&self.platform, &other.platform_profile)
+        {
+            if platform.profile != *platform_profile {
+                return false;
+            }
+        }
+
         true
     }
 }
 
 impl ToString for PowerProfile {
     fn to_string(&self) -> String {
-        format!("PowerProfile(name={}, cpu={:#?})", self.name, self.cpu,)
+        format!(
+            "PowerProfile(name={}, cpu={:#?}, platform={:#?})",
+            self.name, self.cpu, self.platform,
+        )
     }
 }
 
@@ -59,3 +114,35 @@ impl PowerProfileHold {
         }
     }
 }
+
+/// A snapshot of the daemon's state, returned by the Status D-Bus method
+#[derive(Clone, Debug, Deserialize, Serialize, Type)]
+pub(crate) struct Status {
+    pub(crate) active_profile: String,
+    pub(crate) holds: Vec<StatusHold>,
+    pub(crate) drivers: Vec<StatusDriver>,
+    /// Reasons performance is degraded, empty if it isn't
+    pub(crate) performance_degraded: Vec<String>,
+    pub(crate) policies: Vec<StatusPolicy>,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize, Type)]
+pub(crate) struct StatusHold {
+    pub(crate) cookie: u32,
+    pub(crate) profile: String,
+    pub(crate) application_id: String,
+    pub(crate) reason: String,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize, Type)]
+pub(crate) struct StatusDriver {
+    pub(crate) category: String,
+    pub(crate) name: String,
+}
+
+/// The current value of the attributes of a cpufreq policy
+#[derive(Clone, Debug, Deserialize, Serialize, Type)]
+pub(crate) struct StatusPolicy {
+    pub(crate) id: u32,
+    pub(crate) values: BTreeMap<String, String>,
+}